@@ -1,35 +1,452 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+
 use reqwest::Client;
-use serde::Serialize;
-use serde_json::{Value};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tiktoken_rs::cl100k_base_singleton;
+
+use crate::{config::Config, datatypes::StructuredSummary};
+
+/// Room left in the context for the answer, the notes of a whole lecture rarely get longer.
+const ANSWER_TOKENS: usize = 2048;
+/// Room for the part of a transcript a chunk is, see `sumarize`.
+const PART_TOKENS: usize = 64;
+/// Chunks never get smaller than this, a context that leaves less room next to the prompt is too small.
+const MIN_CHUNK_TOKENS: usize = 512;
+/// Between the notes of two parts when they are merged.
+const SEPARATOR: &str = "\n\n---\n\n";
+
+/// The Ollama server notes are summarized with and its default settings, from the config.
+#[derive(Clone, Debug)]
+pub struct Llm {
+    client: Client,
+    /// Base url of the Ollama server.
+    pub url: String,
+    pub model: String,
+    pub temperature: f32,
+    pub top_p: f32,
+    pub repeat_penalty: f32,
+    /// Largest context to run with, see `Llm::context`.
+    pub context: usize,
+    /// Context length of every model asked for so far.
+    contexts: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+/// Settings of a single upload or rerun, `None` keeps the configured one.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SummaryOptions {
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub repeat_penalty: Option<f32>,
+}
+
+impl Llm {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            client: Client::new(),
+            url: config.llm_url.trim_end_matches('/').to_string(),
+            model: config.llm_model.clone(),
+            temperature: config.llm_temperature,
+            top_p: config.llm_top_p,
+            repeat_penalty: config.llm_repeat_penalty,
+            context: config.llm_context,
+            contexts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The context `model` is run with, the configured one unless the model was trained on less.
+    async fn context(&self, model: &str) -> Result<usize, AiError> {
+        if let Some(context) = self.contexts.lock().unwrap().get(model) {
+            return Ok(*context);
+        }
+        let res: Show = self.client.post(format!("{}/api/show", self.url)).json(&serde_json::json!({ "model": model })).send().await?.json().await?;
+        if let Some(error) = res.error {
+            return Err(AiError::Ollama(error));
+        }
+        // the key is prefixed with the architecture, like `qwen2.context_length`
+        let trained = res.model_info.iter().find(|(key, _)| key.ends_with(".context_length")).and_then(|(_, value)| value.as_u64());
+        let context = trained.map_or(self.context, |trained| self.context.min(trained as usize));
+        self.contexts.lock().unwrap().insert(model.to_string(), context);
+        Ok(context)
+    }
+
+    /// The model a summary with these options is written by.
+    pub fn model<'a>(&'a self, options: &'a SummaryOptions) -> &'a str {
+        options.model.as_deref().unwrap_or(&self.model)
+    }
+}
+
+#[derive(Debug)]
+pub enum AiError {
+    /// Ollama couldn't be reached or didn't answer with a chat response
+    Http(reqwest::Error),
+    /// Ollama answered with an error of its own, like a model that isn't pulled
+    Ollama(String),
+    /// a line of the streamed response wasn't the json Ollama sends
+    Json(serde_json::Error),
+    /// the transcript or the notes of its parts can't be made to fit into the context
+    TooLong,
+}
+
+impl fmt::Display for AiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AiError::Http(e) => write!(f, "summary server failed: {}", e),
+            AiError::Ollama(e) => write!(f, "ollama failed: {}", e),
+            AiError::Json(e) => write!(f, "unexpected response from ollama: {}", e),
+            AiError::TooLong => write!(
+                f,
+                "the transcript doesn't fit into the context of the model, raise LLM_CONTEXT"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AiError {}
+
+impl From<reqwest::Error> for AiError {
+    fn from(e: reqwest::Error) -> Self {
+        AiError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for AiError {
+    fn from(e: serde_json::Error) -> Self {
+        AiError::Json(e)
+    }
+}
 
 #[derive(Serialize)]
 struct Query {
     model: String,
     messages: Vec<Message>,
     stream: bool,
+    /// json schema the answer is held to
+    format: Value,
     options: Options
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 struct Message {
     role: String,
     content: String
 }
 
+#[derive(Deserialize)]
+struct Show {
+    #[serde(default)]
+    model_info: HashMap<String, Value>,
+    error: Option<String>
+}
+
+/// One line of the streamed response, with the next few tokens of the message.
+/// Errors come as `{"error": "..."}` instead of a message.
+#[derive(Deserialize)]
+struct Response {
+    message: Option<Message>,
+    error: Option<String>
+}
+
 #[derive(Serialize)]
 struct Options {
     temperature: f32,
     top_p: f32,
-    repeat_penalty: f32
+    repeat_penalty: f32,
+    /// Ollama defaults to a small context and silently drops the start of longer inputs.
+    num_ctx: usize
 }
 
-pub async fn sumarize(text: String) -> String {
+/// Writes notes of a transcript. A transcript too long for the context of the model is
+/// split into chunks of whole lines, so no segment is cut in half. Every chunk gets notes of
+/// its own, which are then merged, in several rounds if they are still too long together.
+/// Notes too long to be merged with any other are shortened first. Nothing is ever sent that
+/// doesn't fit into the context, Ollama would silently drop its start.
+/// The notes of the parts are merged as json, so the merged notes keep the structure.
+/// `draft` gets the notes written so far as markdown whenever the model writes more,
+/// the notes of all parts while they are written and then the merged ones from scratch.
+pub async fn sumarize(llm: &Llm, options: &SummaryOptions, text: String, language: &str, speakers: bool, mut draft: Box<dyn FnMut(&str) + Send>) -> Result<StructuredSummary, AiError> {
+    let context = llm.context(llm.model(options)).await?;
+    let mut prompt = format!("{}\n\n{}", include_str!("summarize_prompt.txt"), language_prompt(language));
+    let mut merge = format!("{}\n\n{}", include_str!("merge_prompt.txt"), language_prompt(language));
+    if speakers {
+        for prompt in [&mut prompt, &mut merge] {
+            prompt.push_str("\n\n");
+            prompt.push_str(include_str!("speakers_prompt.txt"));
+        }
+    }
+    let budget = context.saturating_sub(tokens(&prompt).max(tokens(&merge)) + PART_TOKENS + ANSWER_TOKENS);
+    if budget < MIN_CHUNK_TOKENS {
+        return Err(AiError::TooLong);
+    }
+    if tokens(&text) <= budget {
+        let json = chat(llm, options, context, prompt, text, |json: &str| {
+            if let Some(notes) = partial(json) {
+                draft(&notes.to_markdown());
+            }
+        }).await?;
+        return Ok(serde_json::from_str(&json)?);
+    }
+
+    let parts = chunks(text.lines(), "\n", budget);
+    if parts.iter().any(|part| tokens(part) > budget) {
+        return Err(AiError::TooLong);
+    }
+    let mut notes: Vec<StructuredSummary> = Vec::new();
+    for (i, part) in parts.iter().enumerate() {
+        let prompt = format!(
+            "{}\n\n<part>\nThe transcript is part {} of {} of the lecture. Write notes for this part only.\n</part>",
+            prompt,
+            i + 1,
+            parts.len()
+        );
+        let written = notes.iter().map(|n| format!("{}\n", n.to_markdown())).collect::<String>();
+        let json = chat(llm, options, context, prompt, part.clone(), |json: &str| {
+            if let Some(part) = partial(json) {
+                draft(&format!("{}{}", written, part.to_markdown()));
+            }
+        }).await?;
+        notes.push(serde_json::from_str(&json)?);
+    }
+    loop {
+        let json: Vec<String> = notes.iter().map(serde_json::to_string).collect::<Result<_, _>>()?;
+        let groups = chunks(json.iter().map(String::as_str), SEPARATOR, budget);
+        if groups.len() == 1 && tokens(&groups[0]) <= budget {
+            let json = chat(llm, options, context, merge, groups[0].clone(), |json: &str| {
+                if let Some(notes) = partial(json) {
+                    draft(&notes.to_markdown());
+                }
+            }).await?;
+            return Ok(serde_json::from_str(&json)?);
+        }
+        if groups.len() < notes.len() {
+            notes = Vec::new();
+            for group in groups {
+                let json = chat(llm, options, context, merge.clone(), group, |_: &str| {}).await?;
+                notes.push(serde_json::from_str(&json)?);
+            }
+            continue;
+        }
+
+        // no two notes fit into one merge, so every one is shortened until any two of them do
+        let before: usize = json.iter().map(|json| tokens(json)).sum();
+        let shorten = format!(
+            "{}\n\n<length>\nThese are the notes of a single part, they are too long. Shorten them to at most {} tokens, keep the most important points.\n</length>",
+            merge,
+            budget / 2
+        );
+        notes = Vec::new();
+        for json in json {
+            if tokens(&json) > budget {
+                return Err(AiError::TooLong);
+            }
+            let json = chat(llm, options, context, shorten.clone(), json, |_: &str| {}).await?;
+            notes.push(serde_json::from_str(&json)?);
+        }
+        let after: usize = notes.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>()?.iter().map(|json| tokens(json)).sum();
+        if after >= before {
+            return Err(AiError::TooLong);
+        }
+    }
+}
+
+/// Parses the notes the model has written so far by closing the strings, arrays and objects
+/// it is in the middle of. If it is in the middle of a key, everything up to the last comma
+/// is parsed instead.
+fn partial(json: &str) -> Option<StructuredSummary> {
+    let mut open = Vec::new();
+    let mut string = false;
+    let mut escaped = false;
+    let mut comma: Option<(usize, Vec<char>)> = None;
+    for (i, c) in json.char_indices() {
+        if string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => string = true,
+            '{' => open.push('}'),
+            '[' => open.push(']'),
+            '}' | ']' => {
+                open.pop();
+            }
+            ',' => comma = Some((i, open.clone())),
+            _ => {}
+        }
+    }
+    let close = |json: &str, open: &[char], string: bool| {
+        let mut json = json.to_string();
+        if string {
+            json.push('"');
+        }
+        json.extend(open.iter().rev());
+        serde_json::from_str(&json).ok()
+    };
+    // a dangling backslash would escape the closing quote
+    let whole = if escaped { &json[..json.len() - 1] } else { json };
+    close(whole, &open, string).or_else(|| comma.and_then(|(i, open)| close(&json[..i], &open, false)))
+}
+
+fn language_prompt(language: &str) -> String {
+    format!("<language>\nWrite the notes in {}, the language of the transcript.\n</language>", language)
+}
+
+/// Tokens `text` takes up. Close enough for the models we run, their vocabularies are
+/// mostly the same byte pair encoding.
+fn tokens(text: &str) -> usize {
+    cl100k_base_singleton().encode_ordinary(text).len()
+}
+
+/// Joins `pieces` into as few chunks as possible that each fit in `budget` tokens.
+/// A piece longer than `budget` gets a chunk of its own.
+fn chunks<'a>(pieces: impl Iterator<Item = &'a str>, separator: &str, budget: usize) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    let mut used = 0;
+    for piece in pieces {
+        let size = tokens(piece) + tokens(separator);
+        match out.last_mut() {
+            Some(chunk) if used + size <= budget => {
+                chunk.push_str(separator);
+                chunk.push_str(piece);
+                used += size;
+            }
+            _ => {
+                out.push(piece.to_string());
+                used = size;
+            }
+        }
+    }
+    out
+}
+
+/// Streams the answer of the model, `on_token` gets the text so far after every few tokens.
+async fn chat(llm: &Llm, options: &SummaryOptions, context: usize, prompt: String, text: String, mut on_token: impl FnMut(&str) + Send) -> Result<String, AiError> {
     let query = Query {
-        model: "qwen2.5:14b".to_string(),
-        messages: vec![Message { role: "system".to_string(), content: include_str!("summarize_prompt.txt").to_string() }, Message { role: "user".to_string(), content: text }],
-        stream: false,
-        options: Options { temperature: 0.2, top_p: 0.9, repeat_penalty: 1.1 }
+        model: llm.model(options).to_string(),
+        messages: vec![Message { role: "system".to_string(), content: prompt }, Message { role: "user".to_string(), content: text }],
+        stream: true,
+        format: serde_json::from_str(include_str!("summary_schema.json")).unwrap(),
+        options: Options {
+            temperature: options.temperature.unwrap_or(llm.temperature),
+            top_p: options.top_p.unwrap_or(llm.top_p),
+            repeat_penalty: options.repeat_penalty.unwrap_or(llm.repeat_penalty),
+            num_ctx: context
+        }
     };
-    let res: Value = Client::new().post("http://localhost:11434/api/chat").json(&query).send().await.unwrap().json().await.unwrap();
-    res.get("message").unwrap().get("content").unwrap().to_string()
-}
\ No newline at end of file
+    let mut res = llm.client.post(format!("{}/api/chat", llm.url)).json(&query).send().await?;
+    // errors before the first token, like an unknown model, aren't streamed
+    if !res.status().is_success() {
+        let res: Response = res.json().await?;
+        return Err(AiError::Ollama(res.error.unwrap_or_else(|| "no message in the response".to_string())));
+    }
+
+    let mut content = String::new();
+    let mut buffer = Vec::new();
+    loop {
+        let chunk = res.chunk().await?;
+        match &chunk {
+            Some(bytes) => buffer.extend_from_slice(bytes),
+            // the last line may come without a newline
+            None => buffer.push(b'\n'),
+        }
+        while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            match serde_json::from_slice(&line)? {
+                Response { error: Some(error), .. } => return Err(AiError::Ollama(error)),
+                Response { message: Some(message), .. } => {
+                    content.push_str(&message.content);
+                    on_token(&content);
+                }
+                Response { .. } => {}
+            }
+        }
+        if chunk.is_none() {
+            return Ok(content);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datatypes::Section;
+
+    const NOTES: &str = r#"{"title": "Eigen \"values\"", "sections": [{"heading": "Definition", "points": ["Av = λv", "v ≠ 0"]}], "key_terms": [{"term": "spectrum", "definition": "all eigenvalues"}], "formulas": [], "open_questions": ["Why?"]}"#;
+
+    #[test]
+    fn partial_parses_whole_notes() {
+        assert_eq!(partial(NOTES), serde_json::from_str(NOTES).ok());
+    }
+
+    #[test]
+    fn partial_parses_every_prefix() {
+        let title = "Eigen \"values\"";
+        // from the opening quote of the title on, every prefix is notes with the title written so far
+        let start = NOTES.find(r#""title": ""#).unwrap() + r#""title": ""#.len();
+        for i in (start..=NOTES.len()).filter(|i| NOTES.is_char_boundary(*i)) {
+            let notes = partial(&NOTES[..i]).unwrap_or_else(|| panic!("prefix {} didn't parse", i));
+            assert!(title.starts_with(&notes.title), "prefix {} has title {:?}", i, notes.title);
+            assert!(notes.sections.len() <= 1);
+        }
+        assert_eq!(partial(&NOTES[..start - 1]), None);
+    }
+
+    #[test]
+    fn partial_closes_open_strings_and_arrays() {
+        let notes = partial(r#"{"title": "Eigen", "sections": [{"heading": "Defin"#).unwrap();
+        assert_eq!(notes.title, "Eigen");
+        assert_eq!(
+            notes.sections,
+            [Section {
+                heading: "Defin".to_string(),
+                points: Vec::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn partial_drops_half_written_keys() {
+        let notes = partial(r#"{"title": "Eigen", "sec"#).unwrap();
+        assert_eq!(notes.title, "Eigen");
+        assert!(notes.sections.is_empty());
+    }
+
+    #[test]
+    fn partial_drops_dangling_backslashes() {
+        assert_eq!(partial(r#"{"title": "A \"#).unwrap().title, "A ");
+    }
+
+    #[test]
+    fn chunks_fill_the_budget() {
+        let size = tokens("word") + tokens(" | ");
+        let pieces = ["word"; 5];
+        assert_eq!(
+            chunks(pieces.into_iter(), " | ", 2 * size),
+            ["word | word", "word | word", "word"]
+        );
+        assert_eq!(chunks(pieces.into_iter(), " | ", 10 * size), ["word | word | word | word | word"]);
+    }
+
+    #[test]
+    fn chunks_keep_long_pieces_whole() {
+        let long = "word ".repeat(100);
+        let out = chunks([long.as_str(), "word"].into_iter(), " | ", 10);
+        assert_eq!(out, [long, "word".to_string()]);
+    }
+}
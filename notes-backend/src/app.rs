@@ -1,9 +1,9 @@
-use crate::pages::HomePage;
+use crate::pages::{CoursesPage, HomePage, NotePage};
 use leptos::prelude::*;
 use leptos_meta::{provide_meta_context, MetaTags, Stylesheet, Title};
 use leptos_router::{
     components::{Route, Router, Routes},
-    StaticSegment,
+    ParamSegment, StaticSegment,
 };
 
 pub fn shell(options: LeptosOptions) -> impl IntoView {
@@ -32,9 +32,15 @@ pub fn App() -> impl IntoView {
     view! {
         <Title text="Notes" />
         <Router>
+            <nav class="p-3 text-gray-200">
+                <a class="mr-4 underline" href="/">Notes</a>
+                <a class="underline" href="/courses">Courses</a>
+            </nav>
             <main>
                 <Routes fallback=|| "Page not found.".into_view()>
                     <Route path=StaticSegment("") view=HomePage />
+                    <Route path=StaticSegment("courses") view=CoursesPage />
+                    <Route path=(StaticSegment("notes"), ParamSegment("id")) view=NotePage />
                 </Routes>
             </main>
         </Router>
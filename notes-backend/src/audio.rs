@@ -0,0 +1,398 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Cursor},
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use rubato::{FftFixedIn, Resampler};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+/// Frames decoded wav files and the resampler work in.
+const BLOCK: usize = 1024;
+
+#[derive(Debug)]
+pub enum AudioError {
+    Io(io::Error),
+    Wav(hound::Error),
+    Decode(SymphoniaError),
+    Ffmpeg(String),
+    Resample(String),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AudioError::Io(e) => write!(f, "failed to read audio: {}", e),
+            AudioError::Wav(e) => write!(f, "failed to read wav: {}", e),
+            AudioError::Decode(e) => write!(f, "failed to decode audio: {}", e),
+            AudioError::Ffmpeg(e) => write!(f, "ffmpeg failed: {}", e),
+            AudioError::Resample(e) => write!(f, "failed to resample audio: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+impl From<io::Error> for AudioError {
+    fn from(e: io::Error) -> Self {
+        AudioError::Io(e)
+    }
+}
+
+impl From<hound::Error> for AudioError {
+    fn from(e: hound::Error) -> Self {
+        AudioError::Wav(e)
+    }
+}
+
+impl From<SymphoniaError> for AudioError {
+    fn from(e: SymphoniaError) -> Self {
+        AudioError::Decode(e)
+    }
+}
+
+/// Guesses the file extension of an upload from its magic bytes.
+pub fn detect_extension(bytes: &[u8]) -> Option<&'static str> {
+    let head = &bytes[..bytes.len().min(64)];
+    match head {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some("wav"),
+        [b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', _, ..] => Some("aiff"),
+        [b'f', b'L', b'a', b'C', ..] => Some("flac"),
+        [b'c', b'a', b'f', b'f', ..] => Some("caf"),
+        [b'O', b'g', b'g', b'S', ..] if contains(head, b"OpusHead") => Some("opus"),
+        [b'O', b'g', b'g', b'S', ..] => Some("ogg"),
+        [0x1A, 0x45, 0xDF, 0xA3, ..] if contains(head, b"webm") => Some("webm"),
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => Some("mkv"),
+        [_, _, _, _, b'f', b't', b'y', b'p', b'M', b'4', b'A' | b'B', ..] => Some("m4a"),
+        [_, _, _, _, b'f', b't', b'y', b'p', b'q', b't', ..] => Some("mov"),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some("mp4"),
+        [b'I', b'D', b'3', ..] => Some("mp3"),
+        // adts frame sync, layer bits are always 0 for aac
+        [0xFF, b, ..] if b & 0xF6 == 0xF0 => Some("aac"),
+        [0xFF, b, ..] if b & 0xE0 == 0xE0 => Some("mp3"),
+        _ => None,
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Decodes any supported file into the 16KHz mono f32 samples whisper expects.
+pub fn decode(file: &str) -> Result<Vec<f32>, AudioError> {
+    let path = Path::new(file);
+    if path.extension().is_some_and(|e| e == "wav") {
+        match decode_wav(path) {
+            // hound only reads pcm, wavs holding adpcm or mp3 are left to symphonia
+            Err(AudioError::Wav(_)) => {}
+            samples => return samples,
+        }
+    }
+    match decode_symphonia(path) {
+        Ok(samples) => Ok(samples),
+        // symphonia has no decoder for e.g. opus, ffmpeg is already needed by the desktop app
+        Err(AudioError::Decode(SymphoniaError::Unsupported(_))) => decode_ffmpeg(path),
+        Err(e) => Err(e),
+    }
+}
+
+/// Encodes 16KHz mono samples as a 16 bit wav file.
+pub fn encode_wav(samples: &[f32]) -> Result<Vec<u8>, AudioError> {
+    let mut bytes = Vec::new();
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: 16000,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::new(Cursor::new(&mut bytes), spec)?;
+    for sample in samples {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(bytes)
+}
+
+fn decode_wav(path: &Path) -> Result<Vec<f32>, AudioError> {
+    let reader = WavReader::open(path)?;
+    let spec = reader.spec();
+    let channels = spec.channels as usize;
+    let mut out = To16khz::new(spec.sample_rate)?;
+    let mut frames = Vec::with_capacity(BLOCK * channels);
+    match spec.sample_format {
+        SampleFormat::Float => {
+            for sample in reader.into_samples::<f32>() {
+                frames.push(sample?);
+                if frames.len() == BLOCK * channels {
+                    out.push(&downmix(&frames, channels))?;
+                    frames.clear();
+                }
+            }
+        }
+        SampleFormat::Int => {
+            // hound hands out signed integers for every bit depth, scale them to [-1, 1)
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            for sample in reader.into_samples::<i32>() {
+                frames.push(sample? as f32 / scale);
+                if frames.len() == BLOCK * channels {
+                    out.push(&downmix(&frames, channels))?;
+                    frames.clear();
+                }
+            }
+        }
+    }
+    out.push(&downmix(&frames, channels))?;
+    out.finish()
+}
+
+fn decode_symphonia(path: &Path) -> Result<Vec<f32>, AudioError> {
+    let mss = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let mut format = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )?
+        .format;
+    // video containers carry more than one track, only the audio one has a sample rate
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL && t.codec_params.sample_rate.is_some())
+        .ok_or(SymphoniaError::Unsupported("no audio track"))?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    // every packet is resampled right away, an hour long recording at its original rate would take gigabytes
+    let mut out = To16khz::new(track.codec_params.sample_rate.unwrap())?;
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // a corrupt packet only costs a few milliseconds of audio
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        let buffer = match &mut buffer {
+            Some(buffer) if buffer.capacity() >= decoded.capacity() * spec.channels.count() => {
+                buffer
+            }
+            _ => buffer.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buffer.copy_interleaved_ref(decoded);
+        out.push(&downmix(buffer.samples(), spec.channels.count()))?;
+    }
+
+    out.finish()
+}
+
+fn decode_ffmpeg(path: &Path) -> Result<Vec<f32>, AudioError> {
+    let out = Command::new("ffmpeg")
+        .arg("-i")
+        .arg(path)
+        .arg("-vn")
+        .arg("-ac")
+        .arg("1")
+        .arg("-ar")
+        .arg("16000")
+        .arg("-f")
+        .arg("f32le")
+        .arg("-")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()?;
+    if !out.status.success() {
+        return Err(AudioError::Ffmpeg(
+            String::from_utf8_lossy(&out.stderr)
+                .lines()
+                .last()
+                .unwrap_or_default()
+                .to_string(),
+        ));
+    }
+
+    Ok(out
+        .stdout
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+fn downmix(samples: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+    samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+/// Resamples mono audio to 16KHz as it is decoded, holding on to nothing but the output
+/// and less than one block of input.
+struct To16khz {
+    /// `None` if the audio is 16KHz already
+    resampler: Option<FftFixedIn<f32>>,
+    sample_rate: u32,
+    /// input waiting for a full block
+    pending: Vec<f32>,
+    /// input samples seen so far
+    len: u64,
+    /// output samples still to drop, the resampler starts with this much silence
+    delay: usize,
+    out: Vec<f32>,
+}
+
+impl To16khz {
+    fn new(sample_rate: u32) -> Result<Self, AudioError> {
+        // fft based resampling is band-limited, so nothing above 8KHz folds back into the speech band
+        let resampler = (sample_rate != 16000)
+            .then(|| FftFixedIn::<f32>::new(sample_rate as usize, 16000, BLOCK, 2, 1))
+            .transpose()
+            .map_err(|e| AudioError::Resample(e.to_string()))?;
+        Ok(Self {
+            delay: resampler.as_ref().map_or(0, |r| r.output_delay()),
+            resampler,
+            sample_rate,
+            pending: Vec::new(),
+            len: 0,
+            out: Vec::new(),
+        })
+    }
+
+    fn push(&mut self, samples: &[f32]) -> Result<(), AudioError> {
+        self.len += samples.len() as u64;
+        let Some(resampler) = &mut self.resampler else {
+            self.out.extend_from_slice(samples);
+            return Ok(());
+        };
+        self.pending.extend_from_slice(samples);
+        let mut start = 0;
+        while self.pending.len() - start >= resampler.input_frames_next() {
+            let end = start + resampler.input_frames_next();
+            let resampled = resampler
+                .process(&[&self.pending[start..end]], None)
+                .map_err(|e| AudioError::Resample(e.to_string()))?;
+            emit(&mut self.out, &mut self.delay, &resampled[0]);
+            start = end;
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<f32>, AudioError> {
+        let Some(resampler) = &mut self.resampler else {
+            return Ok(self.out);
+        };
+        let expected = (self.len * 16000 / self.sample_rate as u64) as usize;
+        let resampled = resampler
+            .process_partial(Some(&[&self.pending]), None)
+            .map_err(|e| AudioError::Resample(e.to_string()))?;
+        emit(&mut self.out, &mut self.delay, &resampled[0]);
+        // flush the samples still sitting in the resampler
+        while self.out.len() < expected {
+            let resampled = resampler
+                .process_partial::<&[f32]>(None, None)
+                .map_err(|e| AudioError::Resample(e.to_string()))?;
+            emit(&mut self.out, &mut self.delay, &resampled[0]);
+        }
+        self.out.truncate(expected);
+        Ok(self.out)
+    }
+}
+
+fn emit(out: &mut Vec<f32>, delay: &mut usize, samples: &[f32]) {
+    let skip = (*delay).min(samples.len());
+    *delay -= skip;
+    out.extend_from_slice(&samples[skip..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_containers_from_magic_bytes() {
+        assert_eq!(detect_extension(b"RIFF\0\0\0\0WAVEfmt "), Some("wav"));
+        assert_eq!(detect_extension(b"fLaC\0\0\0\x22"), Some("flac"));
+        assert_eq!(detect_extension(b"OggS\0\x02\0\0\0\0\0\0\0\0OpusHead"), Some("opus"));
+        assert_eq!(detect_extension(b"OggS\0\x02\0\0\0\0\0\0\0\0\x01vorbis"), Some("ogg"));
+        assert_eq!(detect_extension(b"\x1A\x45\xDF\xA3\x9F\x42\x82\x84webm"), Some("webm"));
+        assert_eq!(detect_extension(b"\x1A\x45\xDF\xA3\x9F\x42\x82\x88matroska"), Some("mkv"));
+        assert_eq!(detect_extension(b"\0\0\0\x20ftypM4A \0\0\0\0"), Some("m4a"));
+        assert_eq!(detect_extension(b"\0\0\0\x14ftypqt  \0\0\0\0"), Some("mov"));
+        assert_eq!(detect_extension(b"\0\0\0\x20ftypisom\0\0\x02\0"), Some("mp4"));
+        assert_eq!(detect_extension(b"ID3\x04\0\0\0\0\0\0"), Some("mp3"));
+    }
+
+    #[test]
+    fn tells_aac_and_mp3_frames_apart() {
+        assert_eq!(detect_extension(&[0xFF, 0xF1, 0x50, 0x80]), Some("aac"));
+        assert_eq!(detect_extension(&[0xFF, 0xFB, 0x90, 0x64]), Some("mp3"));
+    }
+
+    fn tone(sample_rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * 440.0 * i as f32 / sample_rate as f32).sin() * 0.5)
+            .collect()
+    }
+
+    #[test]
+    fn resamples_packets_of_any_size() {
+        let mut out = To16khz::new(44100).unwrap();
+        for packet in tone(44100, 44100).chunks(1000) {
+            out.push(packet).unwrap();
+        }
+        let out = out.finish().unwrap();
+        assert_eq!(out.len(), 16000);
+        // away from the edges the tone keeps its level and phase, so the delay was taken off
+        let expected = tone(16000, 16000);
+        for i in 1000..15000 {
+            assert!((out[i] - expected[i]).abs() < 0.02, "sample {}", i);
+        }
+    }
+
+    #[test]
+    fn keeps_16khz_audio() {
+        let samples = tone(16000, 5000);
+        let mut out = To16khz::new(16000).unwrap();
+        out.push(&samples[..3000]).unwrap();
+        out.push(&samples[3000..]).unwrap();
+        assert_eq!(out.finish().unwrap(), samples);
+    }
+
+    #[test]
+    fn rejects_unknown_files() {
+        assert_eq!(detect_extension(b""), None);
+        assert_eq!(detect_extension(b"%PDF-1.7"), None);
+    }
+}
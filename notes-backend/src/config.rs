@@ -0,0 +1,86 @@
+use std::{env, path::PathBuf, str::FromStr, thread};
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// `whisper`, `openai` or `mock`, see `transcription::Transcriber`.
+    pub transcriber: String,
+    /// Base url of the OpenAI compatible transcription server.
+    pub transcription_url: String,
+    pub transcription_api_key: Option<String>,
+    /// Model the transcription server is asked for.
+    pub transcription_model: String,
+    /// Number of jobs processed at once, every one of them holds its recording in memory.
+    pub jobs: usize,
+    /// Number of whisper states transcribing in parallel.
+    pub workers: usize,
+    /// Number of chunks that may wait for a free worker before the jobs sending them block.
+    pub queue: usize,
+    /// Skip the silent parts of recordings before running whisper.
+    pub vad: bool,
+    /// Whisper model used unless a job asks for another one, one of `models::MODELS`.
+    pub model: String,
+    /// Where models are downloaded to and looked up.
+    pub models_dir: PathBuf,
+    /// Never download models, only use the ones already in `models_dir`.
+    pub offline: bool,
+    /// Run whisper on the gpu, only has an effect if a gpu backend feature was enabled.
+    pub gpu: bool,
+    /// Index of the gpu to use.
+    pub gpu_device: i32,
+    /// Cpu threads of every worker.
+    pub threads: usize,
+    /// How different two voices have to sound to be told apart by the diarization,
+    /// lower values find more speakers.
+    pub diarization_threshold: f32,
+    /// Base url of the Ollama server writing the summaries.
+    pub llm_url: String,
+    /// Model summaries are written by unless an upload picks another one.
+    pub llm_model: String,
+    /// Sampling settings of the summaries, lower temperatures stick closer to the transcript.
+    pub llm_temperature: f32,
+    pub llm_top_p: f32,
+    pub llm_repeat_penalty: f32,
+    /// Context window in tokens the summaries run with, capped at the model's own.
+    /// Longer transcripts are summarized in parts and the notes merged.
+    pub llm_context: usize,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            transcriber: var("TRANSCRIBER", "whisper".to_string()),
+            transcription_url: var("TRANSCRIPTION_URL", "https://api.openai.com".to_string()),
+            transcription_api_key: env::var("TRANSCRIPTION_API_KEY").ok(),
+            transcription_model: var("TRANSCRIPTION_MODEL", "whisper-1".to_string()),
+            jobs: var("JOBS", 2).max(1),
+            workers: var("WHISPER_WORKERS", 1).max(1),
+            queue: var("WHISPER_QUEUE", 16).max(1),
+            vad: var("WHISPER_VAD", true),
+            model: var("WHISPER_MODEL", "large-v3-q5_0".to_string()),
+            models_dir: var("WHISPER_MODELS_DIR", PathBuf::from(".")),
+            offline: var("WHISPER_OFFLINE", false),
+            gpu: var("WHISPER_GPU", true),
+            gpu_device: var("WHISPER_GPU_DEVICE", 0),
+            // whisper.cpp's own default
+            threads: var(
+                "WHISPER_THREADS",
+                thread::available_parallelism().map_or(1, |n| n.get().min(4)),
+            )
+            .max(1),
+            diarization_threshold: var("DIARIZATION_THRESHOLD", 0.6),
+            llm_url: var("LLM_URL", "http://localhost:11434".to_string()),
+            llm_model: var("LLM_MODEL", "qwen2.5:14b".to_string()),
+            llm_temperature: var("LLM_TEMPERATURE", 0.2),
+            llm_top_p: var("LLM_TOP_P", 0.9),
+            llm_repeat_penalty: var("LLM_REPEAT_PENALTY", 1.1),
+            llm_context: var("LLM_CONTEXT", 8192),
+        }
+    }
+}
+
+fn var<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
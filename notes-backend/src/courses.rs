@@ -0,0 +1,112 @@
+use leptos::{prelude::*, server_fn::codec::GetUrl};
+
+#[cfg(feature = "ssr")]
+use sqlx::{query, query_as};
+
+use crate::datatypes::Course;
+
+#[cfg(feature = "ssr")]
+use crate::datatypes::AppState;
+
+/// Also fetched by the desktop app for its upload window, hence the fixed GET endpoint.
+#[server(input = GetUrl, endpoint = "courses")]
+pub async fn courses() -> Result<Vec<Course>, ServerFnError> {
+    #[cfg(feature = "ssr")]
+    {
+        let state = expect_context::<AppState>();
+
+        let out = query_as(
+            r#"
+        SELECT id, name, vocabulary FROM courses
+        ORDER BY name
+    "#,
+        )
+        .fetch_all(&state.pool)
+        .await
+        .unwrap();
+
+        Ok(out)
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        Err(ServerFnError::ServerError("Not on server".to_string()))
+    }
+}
+
+#[server]
+pub async fn add_course(name: String) -> Result<(), ServerFnError> {
+    #[cfg(feature = "ssr")]
+    {
+        let state = expect_context::<AppState>();
+
+        query(
+            r#"
+        INSERT INTO courses (name)
+        VALUES (?)
+    "#,
+        )
+        .bind(name.trim())
+        .execute(&state.pool)
+        .await
+        .unwrap();
+
+        Ok(())
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        Err(ServerFnError::ServerError("Not on server".to_string()))
+    }
+}
+
+#[server]
+pub async fn save_course(id: u32, name: String, vocabulary: String) -> Result<(), ServerFnError> {
+    #[cfg(feature = "ssr")]
+    {
+        let state = expect_context::<AppState>();
+
+        query(
+            r#"
+        UPDATE courses
+        SET name = ?, vocabulary = ?
+        WHERE id = ?
+    "#,
+        )
+        .bind(name.trim())
+        .bind(vocabulary.trim())
+        .bind(id)
+        .execute(&state.pool)
+        .await
+        .unwrap();
+
+        Ok(())
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        Err(ServerFnError::ServerError("Not on server".to_string()))
+    }
+}
+
+#[server]
+pub async fn delete_course(id: u32) -> Result<(), ServerFnError> {
+    #[cfg(feature = "ssr")]
+    {
+        let state = expect_context::<AppState>();
+
+        query(
+            r#"
+        DELETE FROM courses
+        WHERE id = ?
+    "#,
+        )
+        .bind(id)
+        .execute(&state.pool)
+        .await
+        .unwrap();
+
+        Ok(())
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        Err(ServerFnError::ServerError("Not on server".to_string()))
+    }
+}
@@ -2,17 +2,285 @@ use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "ssr")]
 use sqlx::{Pool, Sqlite};
+#[cfg(feature = "ssr")]
+use std::sync::Arc;
+
+#[cfg(feature = "ssr")]
+use crate::{ai::Llm, jobs::JobQueue, transcription::Transcriber};
 
 #[cfg(feature = "ssr")]
 #[derive(Clone)]
 pub struct AppState {
     pub pool: Pool<Sqlite>,
+    /// picked by `TRANSCRIBER` at startup
+    pub transcriber: Arc<dyn Transcriber>,
+    pub jobs: JobQueue,
+    /// summarizer settings from the config, uploads may override them
+    pub llm: Llm,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
 pub struct Note {
+    pub id: u32,
     pub name: String,
     pub transcript: Option<String>,
     pub summary: Option<String>,
+    /// the model that wrote the summary
+    pub summary_model: Option<String>,
+    /// the summary written so far while the note is being summarized
+    pub draft: Option<String>,
+    /// title of the structured summary, `None` for notes summarized before summaries were structured
+    pub title: Option<String>,
+    /// Whisper language code of the recording
+    pub language: Option<String>,
+    /// English translation of the transcript, if one was requested
+    pub translation: Option<String>,
+    pub state: Option<JobState>,
+    pub error: Option<String>,
+    pub progress: Option<u8>,
+    pub eta: Option<u32>,
+    /// timestamped segments of the transcript, in order
+    #[cfg_attr(feature = "ssr", sqlx(skip))]
+    pub segments: Vec<Segment>,
+    /// what the hallucination filter took out of the transcript
+    #[cfg_attr(feature = "ssr", sqlx(json))]
+    pub removed: Vec<Removed>,
+}
+
+/// An earlier output of a note, kept when it is transcribed or summarized again.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
+pub struct Version {
+    pub id: u32,
+    /// when the note was redone, `YYYY-MM-DD HH:MM:SS` in UTC
+    pub created_at: String,
+    pub transcript: Option<String>,
+    pub summary: Option<String>,
+    pub summary_model: Option<String>,
+    /// `None` if the summary wasn't structured yet
+    #[cfg_attr(feature = "ssr", sqlx(json(nullable)))]
+    pub structured: Option<StructuredSummary>,
+    pub language: Option<String>,
+    pub translation: Option<String>,
+    #[cfg_attr(feature = "ssr", sqlx(json))]
+    pub segments: Vec<Segment>,
+}
+
+/// The summary of a note rendered for reading.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Summary {
+    pub name: String,
+    /// sanitized html of the markdown summary, `None` until the note is summarized
+    pub html: Option<String>,
+    pub model: Option<String>,
+    /// `None` for notes summarized before summaries were structured
+    pub structured: Option<StructuredSummary>,
+}
+
+/// Notes in the same shape no matter which model wrote them, the model is held to
+/// `summary_schema.json`. Every field defaults so half written notes parse as well.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct StructuredSummary {
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub sections: Vec<Section>,
+    #[serde(default)]
+    pub key_terms: Vec<KeyTerm>,
+    #[serde(default)]
+    pub formulas: Vec<Formula>,
+    /// questions the lecture raised but didn't answer
+    #[serde(default)]
+    pub open_questions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
+pub struct Section {
+    #[serde(default)]
+    pub heading: String,
+    #[serde(default)]
+    #[cfg_attr(feature = "ssr", sqlx(json))]
+    pub points: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
+pub struct KeyTerm {
+    #[serde(default)]
+    pub term: String,
+    #[serde(default)]
+    pub definition: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
+pub struct Formula {
+    /// in LaTeX notation
+    #[serde(default)]
+    pub formula: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+impl StructuredSummary {
+    /// The notes as markdown, stored as the summary for copying and older clients.
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# {}\n", self.title);
+        for section in &self.sections {
+            out.push_str(&format!("\n## {}\n", section.heading));
+            for point in &section.points {
+                out.push_str(&format!("- {}\n", point));
+            }
+        }
+        if !self.key_terms.is_empty() {
+            out.push_str("\n## Key terms\n");
+            for term in &self.key_terms {
+                out.push_str(&format!("- **{}**: {}\n", term.term, term.definition));
+            }
+        }
+        if !self.formulas.is_empty() {
+            out.push_str("\n## Formulas\n");
+            for formula in &self.formulas {
+                out.push_str(&format!("- `{}`: {}\n", formula.formula, formula.description));
+            }
+        }
+        if !self.open_questions.is_empty() {
+            out.push_str("\n## Open questions\n");
+            for question in &self.open_questions {
+                out.push_str(&format!("- {}\n", question));
+            }
+        }
+        out
+    }
+}
+
+/// What a note is redone with.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RerunStep {
+    /// only the transcript, the summary stays as it is
+    Transcription,
+    /// only the summary, from the current transcript
+    Summary,
+    Both,
+}
+
+/// A course whose vocabulary helps whisper with the terms used in its lectures.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
+pub struct Course {
+    pub id: u32,
+    pub name: String,
+    /// terms, names and abbreviations separated by commas or new lines
+    pub vocabulary: String,
+}
+
+/// One piece of the transcript as whisper split it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "ssr", derive(sqlx::FromRow))]
+pub struct Segment {
+    /// position of the segment in the transcript, starting at 0
+    pub index: u32,
+    pub start_ms: u32,
+    pub end_ms: u32,
+    pub text: String,
+    /// average probability of the segment's text tokens, low values hint at a misheard segment
+    pub probability: f32,
+    /// who is speaking if the recording was diarized, 0 is whoever talks the most
+    #[serde(default)]
+    pub speaker: Option<u32>,
+}
+
+impl Segment {
+    /// `Speaker 1` for speaker 0, as it appears in transcripts and subtitles.
+    pub fn speaker_label(&self) -> Option<String> {
+        self.speaker.map(|speaker| format!("Speaker {}", speaker + 1))
+    }
+}
+
+/// A piece of the transcript that was dropped as a whisper hallucination.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Removed {
+    pub start_ms: u32,
+    pub end_ms: u32,
+    /// the whole segment, or only the repeated words cut out of it
+    pub text: String,
+    pub reason: RemovalReason,
+    /// removed from the english translation rather than the transcript
+    #[serde(default)]
+    pub translation: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemovalReason {
+    /// whisper was too unsure about the segment
+    Probability,
+    /// the same words or segments over and over
+    Repetition,
+    /// a phrase whisper learned from subtitles, like `Thanks for watching!`
+    Phrase,
+}
+
+impl RemovalReason {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RemovalReason::Probability => "unlikely",
+            RemovalReason::Repetition => "repeated",
+            RemovalReason::Phrase => "hallucinated",
+        }
+    }
+}
+
+/// Sent over `/events` as json tagged with its `type`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum JobEvent {
+    Progress(Progress),
+    Draft(Draft),
+}
+
+/// The summary of a note while it is being written.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Draft {
+    /// id of the note
+    pub id: u32,
+    /// everything written so far, replaces the previous draft
+    pub text: String,
+}
+
+/// Sent whenever a job changes state or reports progress.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Progress {
+    /// id of the note
+    pub id: u32,
+    pub state: JobState,
+    /// percent of the transcription done
+    pub progress: Option<u8>,
+    /// estimated seconds until the transcription is done
+    pub eta: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "ssr", derive(sqlx::Type), sqlx(rename_all = "lowercase"))]
+pub enum JobState {
+    Queued,
+    Transcribing,
+    Summarizing,
+    Done,
+    Failed,
+}
+
+impl JobState {
+    pub fn label(&self) -> &'static str {
+        match self {
+            JobState::Queued => "Queued",
+            JobState::Transcribing => "Transcribing...",
+            JobState::Summarizing => "Summarizing...",
+            JobState::Done => "Done",
+            JobState::Failed => "Failed",
+        }
+    }
 }
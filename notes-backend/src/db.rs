@@ -0,0 +1,142 @@
+use sqlx::{query, Pool, Sqlite};
+
+/// Creates the tables and brings databases written by older versions up to date.
+pub async fn migrate(pool: &Pool<Sqlite>) -> Result<(), sqlx::Error> {
+    query(
+        r#"
+        CREATE TABLE IF NOT EXISTS entries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            file TEXT NOT NULL,
+            name TEXT NOT NULL,
+            transcript TEXT,
+            summary TEXT
+        )
+    "#,
+    )
+    .execute(pool)
+    .await?;
+
+    query(
+        r#"
+        CREATE TABLE IF NOT EXISTS jobs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            entry_id INTEGER NOT NULL REFERENCES entries(id),
+            state TEXT NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            error TEXT
+        )
+    "#,
+    )
+    .execute(pool)
+    .await?;
+    query(
+        r#"
+        CREATE TABLE IF NOT EXISTS segments (
+            entry_id INTEGER NOT NULL REFERENCES entries(id),
+            "index" INTEGER NOT NULL,
+            start_ms INTEGER NOT NULL,
+            end_ms INTEGER NOT NULL,
+            text TEXT NOT NULL,
+            probability REAL NOT NULL,
+            PRIMARY KEY (entry_id, "index")
+        )
+    "#,
+    )
+    .execute(pool)
+    .await?;
+    query(
+        r#"
+        CREATE TABLE IF NOT EXISTS courses (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            vocabulary TEXT NOT NULL DEFAULT ''
+        )
+    "#,
+    )
+    .execute(pool)
+    .await?;
+    query(
+        r#"
+        CREATE TABLE IF NOT EXISTS versions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            entry_id INTEGER NOT NULL REFERENCES entries(id),
+            created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            transcript TEXT,
+            summary TEXT,
+            language TEXT,
+            translation TEXT,
+            segments TEXT NOT NULL DEFAULT '[]'
+        )
+    "#,
+    )
+    .execute(pool)
+    .await?;
+    // the parts of a structured summary, the title is a column of entries
+    for table in [
+        r#"sections (
+            entry_id INTEGER NOT NULL REFERENCES entries(id),
+            "index" INTEGER NOT NULL,
+            heading TEXT NOT NULL,
+            points TEXT NOT NULL DEFAULT '[]',
+            PRIMARY KEY (entry_id, "index")
+        )"#,
+        r#"key_terms (
+            entry_id INTEGER NOT NULL REFERENCES entries(id),
+            "index" INTEGER NOT NULL,
+            term TEXT NOT NULL,
+            definition TEXT NOT NULL,
+            PRIMARY KEY (entry_id, "index")
+        )"#,
+        r#"formulas (
+            entry_id INTEGER NOT NULL REFERENCES entries(id),
+            "index" INTEGER NOT NULL,
+            formula TEXT NOT NULL,
+            description TEXT NOT NULL,
+            PRIMARY KEY (entry_id, "index")
+        )"#,
+        r#"open_questions (
+            entry_id INTEGER NOT NULL REFERENCES entries(id),
+            "index" INTEGER NOT NULL,
+            question TEXT NOT NULL,
+            PRIMARY KEY (entry_id, "index")
+        )"#,
+    ] {
+        query(&format!("CREATE TABLE IF NOT EXISTS {}", table))
+            .execute(pool)
+            .await?;
+    }
+    add_column(pool, "jobs", "progress INTEGER").await?;
+    add_column(pool, "jobs", "eta INTEGER").await?;
+    add_column(pool, "jobs", "options TEXT").await?;
+    add_column(pool, "jobs", "not_before TEXT").await?;
+    add_column(pool, "entries", "language TEXT").await?;
+    add_column(pool, "entries", "translation TEXT").await?;
+    add_column(pool, "entries", "removed TEXT NOT NULL DEFAULT '[]'").await?;
+    add_column(pool, "segments", "speaker INTEGER").await?;
+    add_column(pool, "entries", "summary_model TEXT").await?;
+    add_column(pool, "versions", "summary_model TEXT").await?;
+    add_column(pool, "entries", "draft TEXT").await?;
+    add_column(pool, "entries", "title TEXT").await?;
+    add_column(pool, "versions", "structured TEXT").await?;
+    // summaries used to be stored as the json string ollama sent, quotes and escapes included
+    for table in ["entries", "versions"] {
+        query(&format!(
+            "UPDATE {} SET summary = json_extract(summary, '$') WHERE json_valid(summary) AND json_type(summary) = 'text'",
+            table
+        ))
+        .execute(pool)
+        .await?;
+    }
+    Ok(())
+}
+
+// `CREATE TABLE IF NOT EXISTS` leaves existing databases alone, so columns added later go through here
+async fn add_column(pool: &Pool<Sqlite>, table: &str, column: &str) -> Result<(), sqlx::Error> {
+    match query(&format!("ALTER TABLE {} ADD COLUMN {}", table, column))
+        .execute(pool)
+        .await
+    {
+        Err(e) if !e.to_string().contains("duplicate column name") => Err(e),
+        _ => Ok(()),
+    }
+}
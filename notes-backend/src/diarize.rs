@@ -0,0 +1,363 @@
+use std::f32::consts::PI;
+
+use realfft::RealFftPlanner;
+
+use crate::datatypes::Segment;
+
+/// 25ms frames every 10ms at 16KHz, the usual framing for speech features.
+const FRAME: usize = 400;
+const STEP: usize = 160;
+const FFT: usize = 512;
+const MELS: usize = 24;
+/// Cepstral coefficients 1 to 12, coefficient 0 is only the loudness.
+const COEFFS: usize = 12;
+/// Shorter segments say too little about the voice to start a speaker of their own,
+/// they get the closest speaker found in the longer ones.
+const MIN_SEGMENT_MS: u32 = 1500;
+/// A speaker needs at least this much speech, anything less is a cough or a door.
+const MIN_SPEAKER_MS: u32 = 5000;
+/// k-means rounds after the first pass.
+const ROUNDS: usize = 5;
+
+/// Labels every segment with a speaker by clustering a spectral fingerprint of its audio.
+/// Speaker 0 talks the most, in a lecture that is the lecturer. Without any segment long enough
+/// to tell, every segment is speaker 0.
+/// `threshold` is the cosine distance above which two segments count as different voices.
+pub fn diarize(samples: &[f32], segments: &mut [Segment], threshold: f32) {
+    let mut embeddings: Vec<Option<Vec<f32>>> = segments
+        .iter()
+        .map(|s| {
+            let start = (s.start_ms as usize * 16).min(samples.len());
+            let end = (s.end_ms as usize * 16).clamp(start, samples.len());
+            embed(&samples[start..end])
+        })
+        .collect();
+    standardize(&mut embeddings);
+
+    let long: Vec<usize> = (0..segments.len())
+        .filter(|i| {
+            embeddings[*i].is_some()
+                && segments[*i].end_ms.saturating_sub(segments[*i].start_ms) >= MIN_SEGMENT_MS
+        })
+        .collect();
+    let duration = |i: usize| segments[i].end_ms.saturating_sub(segments[i].start_ms);
+
+    // every segment too far from all speakers so far starts a new one
+    let mut centroids: Vec<Vec<f32>> = Vec::new();
+    for &i in &long {
+        let embedding = embeddings[i].as_ref().unwrap();
+        if nearest(&centroids, embedding).is_none_or(|(_, d)| d > threshold) {
+            centroids.push(embedding.clone());
+        }
+    }
+    for round in 0..ROUNDS {
+        let assigned: Vec<usize> = long
+            .iter()
+            .map(|&i| {
+                nearest(&centroids, embeddings[i].as_ref().unwrap())
+                    .unwrap()
+                    .0
+            })
+            .collect();
+        let mut speech = vec![0; centroids.len()];
+        let mut sums = vec![vec![0.0; 2 * COEFFS]; centroids.len()];
+        for (&i, &c) in long.iter().zip(&assigned) {
+            speech[c] += duration(i);
+            for (sum, x) in sums[c].iter_mut().zip(embeddings[i].as_ref().unwrap()) {
+                *sum += x * duration(i) as f32;
+            }
+        }
+        let mut next: Vec<(u32, Vec<f32>)> = speech
+            .into_iter()
+            .zip(sums)
+            .filter(|(speech, _)| *speech > 0)
+            .map(|(speech, sum)| (speech, sum.into_iter().map(|x| x / speech as f32).collect()))
+            .collect();
+        if round + 1 < ROUNDS {
+            // a short recording still keeps its main speaker
+            let most = next.iter().map(|(speech, _)| *speech).max().unwrap_or(0);
+            next.retain(|(speech, _)| *speech >= MIN_SPEAKER_MS.min(most));
+            merge_close(&mut next, threshold);
+        }
+        next.sort_by_key(|(speech, _)| std::cmp::Reverse(*speech));
+        centroids = next.into_iter().map(|(_, centroid)| centroid).collect();
+        // no segment was long enough to tell voices apart, everyone is speaker 0 below
+        if centroids.is_empty() {
+            break;
+        }
+    }
+
+    let mut last = 0;
+    for (segment, embedding) in segments.iter_mut().zip(&embeddings) {
+        // a segment too short to tell belongs to whoever spoke before it
+        let speaker = embedding
+            .as_ref()
+            .and_then(|e| nearest(&centroids, e))
+            .map_or(last, |(c, _)| c as u32);
+        segment.speaker = Some(speaker);
+        last = speaker;
+    }
+}
+
+/// Gives translated segments the speaker of the transcript segment they overlap the most.
+pub fn assign(transcript: &[Segment], segments: &mut [Segment]) {
+    for segment in segments {
+        segment.speaker = transcript
+            .iter()
+            .max_by_key(|s| {
+                s.end_ms
+                    .min(segment.end_ms)
+                    .saturating_sub(s.start_ms.max(segment.start_ms))
+            })
+            .and_then(|s| s.speaker);
+    }
+}
+
+/// Mean and standard deviation of the mel cepstrum of the louder frames, `None` if the
+/// segment is shorter than a frame.
+fn embed(samples: &[f32]) -> Option<Vec<f32>> {
+    if samples.len() < FRAME {
+        return None;
+    }
+    let mut planner = RealFftPlanner::<f32>::new();
+    let fft = planner.plan_fft_forward(FFT);
+    let mut input = fft.make_input_vec();
+    let mut spectrum = fft.make_output_vec();
+    let window: Vec<f32> = (0..FRAME)
+        .map(|i| 0.54 - 0.46 * (2.0 * PI * i as f32 / (FRAME - 1) as f32).cos())
+        .collect();
+    let filters = mel_filters(spectrum.len());
+
+    let mut frames: Vec<(f32, Vec<f32>)> = Vec::new();
+    for frame in samples.windows(FRAME).step_by(STEP) {
+        input.fill(0.0);
+        for ((x, s), w) in input.iter_mut().zip(frame).zip(&window) {
+            *x = s * w;
+        }
+        fft.process(&mut input, &mut spectrum).unwrap();
+        let power: Vec<f32> = spectrum.iter().map(|c| c.norm_sqr()).collect();
+        let mels: Vec<f32> = filters
+            .iter()
+            .map(|filter| {
+                let energy: f32 = filter.iter().map(|(bin, w)| power[*bin] * w).sum();
+                energy.max(1e-10).ln()
+            })
+            .collect();
+        let cepstrum = (1..=COEFFS)
+            .map(|k| {
+                mels.iter()
+                    .enumerate()
+                    .map(|(m, x)| x * (PI * k as f32 * (m as f32 + 0.5) / MELS as f32).cos())
+                    .sum()
+            })
+            .collect();
+        frames.push((mels.iter().sum(), cepstrum));
+    }
+
+    // the quieter frames are pauses between words, they sound like the room and not the voice
+    let mut energies: Vec<f32> = frames.iter().map(|(e, _)| *e).collect();
+    energies.sort_by(f32::total_cmp);
+    let floor = energies[energies.len() * 3 / 10];
+    let voiced: Vec<&Vec<f32>> = frames
+        .iter()
+        .filter(|(e, _)| *e >= floor)
+        .map(|(_, c)| c)
+        .collect();
+
+    let n = voiced.len() as f32;
+    let mut embedding = vec![0.0; 2 * COEFFS];
+    for k in 0..COEFFS {
+        let mean = voiced.iter().map(|c| c[k]).sum::<f32>() / n;
+        let variance = voiced.iter().map(|c| (c[k] - mean).powi(2)).sum::<f32>() / n;
+        embedding[k] = mean;
+        embedding[COEFFS + k] = variance.sqrt();
+    }
+    Some(embedding)
+}
+
+/// Triangular filters evenly spaced on the mel scale from 60Hz to 7.6KHz,
+/// as `(fft bin, weight)` pairs.
+fn mel_filters(bins: usize) -> Vec<Vec<(usize, f32)>> {
+    let mel = |hz: f32| 2595.0 * (1.0 + hz / 700.0).log10();
+    let hz = |mel: f32| 700.0 * (10f32.powf(mel / 2595.0) - 1.0);
+    let (low, high) = (mel(60.0), mel(7600.0));
+    let edges: Vec<f32> = (0..MELS + 2)
+        .map(|i| hz(low + (high - low) * i as f32 / (MELS + 1) as f32) * FFT as f32 / 16000.0)
+        .collect();
+    edges
+        .windows(3)
+        .map(|edge| {
+            (0..bins)
+                .filter_map(|bin| {
+                    let b = bin as f32;
+                    let weight = if b < edge[1] {
+                        (b - edge[0]) / (edge[1] - edge[0])
+                    } else {
+                        (edge[2] - b) / (edge[2] - edge[1])
+                    };
+                    (weight > 0.0).then_some((bin, weight))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Scales every dimension to zero mean and unit variance over the recording,
+/// so the higher coefficients count as much as the large first ones.
+fn standardize(embeddings: &mut [Option<Vec<f32>>]) {
+    let n = embeddings.iter().flatten().count() as f32;
+    if n < 2.0 {
+        return;
+    }
+    for k in 0..2 * COEFFS {
+        let mean = embeddings.iter().flatten().map(|e| e[k]).sum::<f32>() / n;
+        let deviation = (embeddings
+            .iter()
+            .flatten()
+            .map(|e| (e[k] - mean).powi(2))
+            .sum::<f32>()
+            / n)
+            .sqrt()
+            .max(1e-6);
+        for e in embeddings.iter_mut().flatten() {
+            e[k] = (e[k] - mean) / deviation;
+        }
+    }
+}
+
+/// Joins speakers whose centroids ended up closer than `threshold`.
+fn merge_close(clusters: &mut Vec<(u32, Vec<f32>)>, threshold: f32) {
+    loop {
+        let mut closest: Option<(usize, usize, f32)> = None;
+        for i in 0..clusters.len() {
+            for j in i + 1..clusters.len() {
+                let d = distance(&clusters[i].1, &clusters[j].1);
+                if d < threshold && closest.is_none_or(|(_, _, c)| d < c) {
+                    closest = Some((i, j, d));
+                }
+            }
+        }
+        let Some((i, j, _)) = closest else {
+            return;
+        };
+        let (speech, centroid) = clusters.remove(j);
+        let (total, joined) = &mut clusters[i];
+        for (a, b) in joined.iter_mut().zip(centroid) {
+            *a = (*a * *total as f32 + b * speech as f32) / (*total + speech) as f32;
+        }
+        *total += speech;
+    }
+}
+
+fn nearest(centroids: &[Vec<f32>], embedding: &[f32]) -> Option<(usize, f32)> {
+    centroids
+        .iter()
+        .map(|c| distance(c, embedding))
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// Cosine distance, 0 for the same direction and 2 for opposite ones.
+fn distance(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    1.0 - dot / (norm(a) * norm(b)).max(1e-10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start_ms: u32, end_ms: u32) -> Segment {
+        Segment {
+            index: 0,
+            start_ms,
+            end_ms,
+            text: String::new(),
+            probability: 1.0,
+            speaker: None,
+        }
+    }
+
+    /// A voice as a buzz of harmonics of `pitch` Hz, fading in and out like syllables.
+    fn voice(pitch: f32, brightness: f32, ms: u32) -> Vec<f32> {
+        (0..ms as usize * 16)
+            .map(|i| {
+                let t = i as f32 / 16000.0;
+                let syllable = 0.6 + 0.4 * (2.0 * PI * 4.0 * t).sin();
+                let buzz: f32 = (1..20)
+                    .map(|h| (2.0 * PI * pitch * h as f32 * t).sin() * brightness.powi(h))
+                    .sum();
+                buzz * syllable * 0.1
+            })
+            .collect()
+    }
+
+    /// Alternating turns of a low, dull voice and a high, bright one, the low one talking more.
+    fn conversation() -> (Vec<f32>, Vec<Segment>, Vec<u32>) {
+        let turns = [
+            (110.0, 0.5, 4000),
+            (260.0, 0.9, 3000),
+            (110.0, 0.5, 4000),
+            (260.0, 0.9, 3000),
+            (110.0, 0.5, 4000),
+        ];
+        let (mut samples, mut segments, mut voices) = (Vec::new(), Vec::new(), Vec::new());
+        for (pitch, brightness, ms) in turns {
+            let start = (samples.len() / 16) as u32;
+            samples.extend(voice(pitch, brightness, ms));
+            segments.push(segment(start, start + ms));
+            voices.push(if pitch < 200.0 { 0 } else { 1 });
+        }
+        (samples, segments, voices)
+    }
+
+    #[test]
+    fn tells_two_voices_apart() {
+        let (samples, mut segments, voices) = conversation();
+        diarize(&samples, &mut segments, 0.6);
+        let speakers: Vec<u32> = segments.iter().map(|s| s.speaker.unwrap()).collect();
+        // the voice talking the most is speaker 0
+        assert_eq!(speakers, voices);
+    }
+
+    #[test]
+    fn short_segments_get_the_closest_speaker() {
+        let (samples, mut segments, _) = conversation();
+        // half a second of the high voice inside its second turn
+        segments.insert(4, segment(11_000, 11_500));
+        diarize(&samples, &mut segments, 0.6);
+        assert_eq!(segments[4].speaker, Some(1));
+    }
+
+    #[test]
+    fn segments_ending_before_they_start_get_the_last_speaker() {
+        let (samples, mut segments, _) = conversation();
+        // whisper sometimes hands out an end timestamp in front of the start
+        segments.insert(2, segment(7000, 6000));
+        diarize(&samples, &mut segments, 0.6);
+        assert_eq!(segments[2].speaker, segments[1].speaker);
+        assert_eq!(segments[2].speaker, Some(1));
+    }
+
+    #[test]
+    fn only_short_segments_are_all_speaker_0() {
+        let (samples, _, _) = conversation();
+        let mut segments: Vec<Segment> =
+            (0..18).map(|i| segment(i * 1000, i * 1000 + 900)).collect();
+        diarize(&samples, &mut segments, 0.6);
+        assert!(segments.iter().all(|s| s.speaker == Some(0)));
+    }
+
+    #[test]
+    fn translations_get_the_speaker_they_overlap_most() {
+        let mut transcript = vec![segment(0, 4000), segment(4000, 7000)];
+        transcript[0].speaker = Some(0);
+        transcript[1].speaker = Some(1);
+        let mut translation = vec![segment(0, 3000), segment(3500, 7000), segment(7000, 6000)];
+        assign(&transcript, &mut translation);
+        let speakers: Vec<_> = translation.iter().map(|s| s.speaker).collect();
+        // the backwards segment overlaps nothing and ends up with the last speaker
+        assert_eq!(speakers, [Some(0), Some(1), Some(1)]);
+    }
+}
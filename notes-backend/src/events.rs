@@ -0,0 +1,27 @@
+use std::convert::Infallible;
+
+use axum::{
+    response::sse::{Event, KeepAlive, Sse},
+    Extension,
+};
+use futures_util::{stream, Stream};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::datatypes::AppState;
+
+/// Streams job progress and summary drafts to the home page as server-sent events.
+pub async fn events(
+    Extension(state): Extension<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = stream::unfold(state.jobs.subscribe(), |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((Ok(Event::default().json_data(event).unwrap()), rx)),
+                // only the latest progress and draft matter, skipped updates are fine
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
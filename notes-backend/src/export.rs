@@ -0,0 +1,188 @@
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use sqlx::{query_as, query_scalar};
+
+use crate::{
+    datatypes::{AppState, Segment},
+    query::structured_summary,
+};
+
+/// Serves the segments of a note as a subtitle file, `format` is `srt` or `vtt`,
+/// or its structured summary as `md` or `json`.
+pub async fn export(
+    Extension(state): Extension<AppState>,
+    Path((id, format)): Path<(u32, String)>,
+) -> Response {
+    let content_type = match format.as_str() {
+        "srt" => "application/x-subrip",
+        "vtt" => "text/vtt",
+        "md" => "text/markdown",
+        "json" => "application/json",
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let Ok(name): Result<Option<String>, _> = query_scalar(
+        r#"
+        SELECT name FROM entries
+        WHERE id = ?
+    "#,
+    )
+    .bind(id)
+    .fetch_optional(&state.pool)
+    .await
+    else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let Some(name) = name else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let out = if format == "md" || format == "json" {
+        let Ok(summary) = structured_summary(&state.pool, id).await else {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+        let Some(summary) = summary else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        if format == "md" {
+            summary.to_markdown()
+        } else {
+            serde_json::to_string_pretty(&summary).unwrap()
+        }
+    } else {
+        let Ok(out) = subtitles(&state, id, &format).await else {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+        out
+    };
+
+    // keep the header value plain ascii, the name is free text from the upload
+    let file_name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || " -_".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                format!("{}; charset=utf-8", content_type),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.{}\"", file_name, format),
+            ),
+        ],
+        out,
+    )
+        .into_response()
+}
+
+async fn subtitles(state: &AppState, id: u32, format: &str) -> Result<String, sqlx::Error> {
+    let separator = if format == "srt" { ',' } else { '.' };
+    let segments: Vec<Segment> = query_as(
+        r#"
+        SELECT "index", start_ms, end_ms, text, probability, speaker FROM segments
+        WHERE entry_id = ?
+        ORDER BY "index"
+    "#,
+    )
+    .bind(id)
+    .fetch_all(&state.pool)
+    .await?;
+
+    let mut out = String::new();
+    if format == "vtt" {
+        out.push_str("WEBVTT\n\n");
+    }
+    for (i, segment) in segments.iter().enumerate() {
+        let text = cue_text(segment, format);
+        // srt cues are numbered from 1, webvtt only needs them to be unique
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            timestamp(segment.start_ms, separator),
+            timestamp(segment.end_ms, separator),
+            text
+        ));
+    }
+    Ok(out)
+}
+
+/// Webvtt has voice tags for speakers, srt players show them as part of the text.
+fn cue_text(segment: &Segment, format: &str) -> String {
+    match (segment.speaker_label(), format) {
+        (Some(speaker), "vtt") => format!("<v {}>{}", escape(&speaker), escape(&segment.text)),
+        (None, "vtt") => escape(&segment.text),
+        (Some(speaker), _) => format!("{}: {}", speaker, segment.text),
+        (None, _) => segment.text.clone(),
+    }
+}
+
+/// Webvtt cue text is markup, a `<` or `&` in the transcript would start a tag or an entity
+/// and `-->` would end the cue.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// `hh:mm:ss,mmm` for srt and `hh:mm:ss.mmm` for webvtt.
+fn timestamp(ms: u32, separator: char) -> String {
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_have_fixed_width() {
+        assert_eq!(timestamp(0, ','), "00:00:00,000");
+        assert_eq!(timestamp(3_723_004, ','), "01:02:03,004");
+        assert_eq!(timestamp(59_999, '.'), "00:00:59.999");
+    }
+
+    #[test]
+    fn timestamps_count_hours_past_a_day() {
+        assert_eq!(timestamp(100 * 3_600_000, '.'), "100:00:00.000");
+    }
+
+    #[test]
+    fn escapes_webvtt_cue_text() {
+        let segment = Segment {
+            index: 0,
+            start_ms: 0,
+            end_ms: 1000,
+            text: "x < y && y --> z".to_string(),
+            probability: 1.0,
+            speaker: None,
+        };
+        assert_eq!(cue_text(&segment, "vtt"), "x &lt; y &amp;&amp; y --&gt; z");
+        assert_eq!(cue_text(&segment, "srt"), "x < y && y --> z");
+        let segment = Segment {
+            speaker: Some(0),
+            ..segment
+        };
+        assert_eq!(
+            cue_text(&segment, "vtt"),
+            "<v Speaker 1>x &lt; y &amp;&amp; y --&gt; z"
+        );
+    }
+}
@@ -0,0 +1,873 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, query_scalar, types::Json, FromRow, Pool, Sqlite, SqliteConnection};
+use tokio::{
+    spawn,
+    sync::{broadcast, watch, Notify, Semaphore},
+    time::{sleep, timeout},
+};
+
+use whisper_rs::{get_lang_id, get_lang_str_full};
+
+use crate::{
+    ai::{sumarize, SummaryOptions},
+    datatypes::{AppState, Draft, JobEvent, JobState, Progress, RerunStep, StructuredSummary},
+    transcription::{format_segments, Transcript, TranscriptionOptions},
+};
+
+/// A failing job is retried until it has been attempted this many times.
+const MAX_ATTEMPTS: u32 = 3;
+/// Wait before the first retry, every further one waits four times as long,
+/// so a restarting Ollama or a short network outage doesn't use up all attempts.
+const RETRY_DELAY_SECS: u32 = 60;
+/// The summary written so far is saved and sent at most this often,
+/// every token would keep the database busy without looking any more live.
+const DRAFT_INTERVAL: Duration = Duration::from_millis(500);
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The processing queue, persisted in the `jobs` table so no upload is lost on a restart.
+#[derive(Clone)]
+pub struct JobQueue {
+    pool: Pool<Sqlite>,
+    notify: Arc<Notify>,
+    events: broadcast::Sender<JobEvent>,
+}
+
+/// Everything chosen at upload time, stored with the job so a retry after a restart behaves the same.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct JobOptions {
+    #[serde(flatten)]
+    pub transcription: TranscriptionOptions,
+    #[serde(default)]
+    pub summary: SummaryOptions,
+    /// Set when an already processed note is redone, `None` for uploads.
+    #[serde(default)]
+    pub rerun: Option<RerunStep>,
+}
+
+#[derive(FromRow, Clone)]
+struct Job {
+    id: u32,
+    entry_id: u32,
+    attempts: u32,
+    options: Option<Json<JobOptions>>,
+}
+
+impl JobQueue {
+    pub fn new(pool: Pool<Sqlite>) -> Self {
+        Self {
+            pool,
+            notify: Arc::new(Notify::new()),
+            events: broadcast::channel(64).0,
+        }
+    }
+
+    /// Every state and progress change and every draft of any job, for the live view on the home page.
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, entry_id: u32, state: JobState, progress: Option<u8>, eta: Option<u32>) {
+        // nobody listening isn't an error
+        let _ = self.events.send(JobEvent::Progress(Progress {
+            id: entry_id,
+            state,
+            progress,
+            eta,
+        }));
+    }
+
+    pub async fn enqueue(&self, entry_id: u32, options: JobOptions) -> Result<(), sqlx::Error> {
+        query(
+            r#"
+            INSERT INTO jobs (entry_id, state, options)
+            VALUES (?, ?, ?)
+        "#,
+        )
+        .bind(entry_id)
+        .bind(JobState::Queued)
+        .bind(Json(options))
+        .execute(&self.pool)
+        .await?;
+        self.queued(entry_id);
+        Ok(())
+    }
+
+    /// Like `enqueue`, but as part of a transaction and only if the entry has no job that is still
+    /// queued or running. `false` if it has one. Call `queued` once the transaction is committed.
+    pub async fn enqueue_idle(
+        conn: &mut SqliteConnection,
+        entry_id: u32,
+        options: JobOptions,
+    ) -> Result<bool, sqlx::Error> {
+        Ok(query(
+            r#"
+            INSERT INTO jobs (entry_id, state, options)
+            SELECT ?, ?, ?
+            WHERE NOT EXISTS (
+                SELECT 1 FROM jobs
+                WHERE entry_id = ? AND state IN (?, ?, ?)
+            )
+        "#,
+        )
+        .bind(entry_id)
+        .bind(JobState::Queued)
+        .bind(Json(options))
+        .bind(entry_id)
+        .bind(JobState::Queued)
+        .bind(JobState::Transcribing)
+        .bind(JobState::Summarizing)
+        .execute(conn)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Tells the queue and the home page about a job added by `enqueue_idle`.
+    pub fn queued(&self, entry_id: u32) {
+        self.publish(entry_id, JobState::Queued, None, None);
+        self.notify.notify_one();
+    }
+
+    /// Puts the last job of an entry back into the queue if it failed, with its attempts reset
+    /// and its options unchanged. `false` if it didn't fail.
+    pub async fn retry(&self, entry_id: u32) -> Result<bool, sqlx::Error> {
+        let retried = query(
+            r#"
+            UPDATE jobs
+            SET state = ?, attempts = 0, error = NULL, not_before = NULL
+            WHERE id = (SELECT MAX(id) FROM jobs WHERE entry_id = ?) AND state = ?
+        "#,
+        )
+        .bind(JobState::Queued)
+        .bind(entry_id)
+        .bind(JobState::Failed)
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if retried {
+            self.publish(entry_id, JobState::Queued, None, None);
+            self.notify.notify_one();
+        }
+        Ok(retried)
+    }
+
+    /// Requeues jobs that were interrupted by a shutdown and creates jobs for
+    /// entries uploaded before the queue existed. A job that was interrupted on its
+    /// last attempt fails instead, a recording that crashes the server would
+    /// otherwise be picked up again after every restart.
+    pub async fn recover(&self) -> Result<(), sqlx::Error> {
+        query(
+            r#"
+            UPDATE jobs
+            SET state = CASE WHEN attempts >= ? THEN ? ELSE ? END,
+                error = CASE WHEN attempts >= ? THEN 'interrupted too often' ELSE error END
+            WHERE state IN (?, ?)
+        "#,
+        )
+        .bind(MAX_ATTEMPTS)
+        .bind(JobState::Failed)
+        .bind(JobState::Queued)
+        .bind(MAX_ATTEMPTS)
+        .bind(JobState::Transcribing)
+        .bind(JobState::Summarizing)
+        .execute(&self.pool)
+        .await?;
+
+        query(
+            r#"
+            INSERT INTO jobs (entry_id, state)
+            SELECT id, ? FROM entries
+            WHERE summary IS NULL AND id NOT IN (SELECT entry_id FROM jobs)
+        "#,
+        )
+        .bind(JobState::Queued)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn claim(&self) -> Result<Option<Job>, sqlx::Error> {
+        let job: Option<Job> = query_as(
+            r#"
+            UPDATE jobs
+            SET state = ?, attempts = attempts + 1, progress = NULL, eta = NULL
+            WHERE id = (
+                SELECT id FROM jobs
+                WHERE state = ? AND (not_before IS NULL OR not_before <= datetime('now'))
+                ORDER BY id LIMIT 1
+            )
+            RETURNING id, entry_id, attempts, options
+        "#,
+        )
+        .bind(JobState::Transcribing)
+        .bind(JobState::Queued)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some(job) = &job {
+            self.publish(job.entry_id, JobState::Transcribing, None, None);
+        }
+        Ok(job)
+    }
+
+    /// Waits for a new job, or until the next failed job may be retried.
+    async fn wait(&self) {
+        let delay: Option<i64> = query_scalar(
+            r#"
+            SELECT MIN(strftime('%s', not_before) - strftime('%s', 'now')) FROM jobs
+            WHERE state = ? AND not_before IS NOT NULL
+        "#,
+        )
+        .bind(JobState::Queued)
+        .fetch_one(&self.pool)
+        .await
+        .unwrap_or_else(|e| {
+            eprintln!("failed to look up the next retry: {}", e);
+            None
+        });
+        match delay {
+            Some(seconds) => {
+                let delay = Duration::from_secs(seconds.max(1) as u64);
+                let _ = timeout(delay, self.notify.notified()).await;
+            }
+            None => self.notify.notified().await,
+        }
+    }
+
+    async fn set_options(&self, job: &Job, options: &JobOptions) -> Result<(), sqlx::Error> {
+        query(
+            r#"
+            UPDATE jobs
+            SET options = ?
+            WHERE id = ?
+        "#,
+        )
+        .bind(Json(options))
+        .bind(job.id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn set_state(&self, job: &Job, state: JobState) -> Result<(), sqlx::Error> {
+        query(
+            r#"
+            UPDATE jobs
+            SET state = ?, progress = NULL, eta = NULL
+            WHERE id = ?
+        "#,
+        )
+        .bind(state)
+        .bind(job.id)
+        .execute(&self.pool)
+        .await?;
+        self.publish(job.entry_id, state, None, None);
+        Ok(())
+    }
+
+    async fn set_progress(
+        &self,
+        job: &Job,
+        progress: u8,
+        eta: Option<u32>,
+    ) -> Result<(), sqlx::Error> {
+        query(
+            r#"
+            UPDATE jobs
+            SET progress = ?, eta = ?
+            WHERE id = ?
+        "#,
+        )
+        .bind(progress)
+        .bind(eta)
+        .bind(job.id)
+        .execute(&self.pool)
+        .await?;
+        self.publish(job.entry_id, JobState::Transcribing, Some(progress), eta);
+        Ok(())
+    }
+
+    async fn set_draft(&self, job: &Job, text: &str) -> Result<(), sqlx::Error> {
+        query(
+            r#"
+            UPDATE entries
+            SET draft = ?
+            WHERE id = ?
+        "#,
+        )
+        .bind(text)
+        .bind(job.entry_id)
+        .execute(&self.pool)
+        .await?;
+        let _ = self.events.send(JobEvent::Draft(Draft {
+            id: job.entry_id,
+            text: text.to_string(),
+        }));
+        Ok(())
+    }
+
+    async fn fail(&self, job: &Job, error: String) -> Result<(), sqlx::Error> {
+        let state = if job.attempts < MAX_ATTEMPTS {
+            JobState::Queued
+        } else {
+            JobState::Failed
+        };
+        let delay = RETRY_DELAY_SECS * 4u32.pow(job.attempts.saturating_sub(1));
+        query(
+            r#"
+            UPDATE jobs
+            SET state = ?, error = ?, not_before = datetime('now', ?)
+            WHERE id = ?
+        "#,
+        )
+        .bind(state)
+        .bind(error)
+        .bind(format!("+{} seconds", delay))
+        .bind(job.id)
+        .execute(&self.pool)
+        .await?;
+        self.publish(job.entry_id, state, None, None);
+        // wakes up the queue to wait for the retry
+        if state == JobState::Queued {
+            self.notify.notify_one();
+        }
+        Ok(())
+    }
+}
+
+/// Works through the queue forever, processing at most `concurrency` jobs at once.
+pub async fn run(state: AppState, concurrency: usize) {
+    let permits = Arc::new(Semaphore::new(concurrency));
+    loop {
+        let permit = permits.clone().acquire_owned().await.unwrap();
+        let job = match state.jobs.claim().await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                drop(permit);
+                state.jobs.wait().await;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("failed to claim job: {}", e);
+                drop(permit);
+                state.jobs.wait().await;
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        spawn(async move {
+            let _permit = permit;
+            // a panic inside the job is reported like any other failure instead of leaving it stuck
+            let error = match spawn(process(state.clone(), job.clone())).await {
+                Ok(Ok(())) => return,
+                Ok(Err(e)) => e.to_string(),
+                Err(e) => e.to_string(),
+            };
+            eprintln!("job {} failed: {}", job.id, error);
+            if let Err(e) = state.jobs.fail(&job, error).await {
+                eprintln!("failed to update job {}: {}", job.id, e);
+            }
+        });
+    }
+}
+
+async fn process(state: AppState, job: Job) -> Result<(), BoxError> {
+    let options = job.options.clone().unwrap_or_default().0;
+    let (file, transcript, language, translation): (
+        String,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+    ) = query_as(
+        r#"
+        SELECT file, transcript, language, translation FROM entries
+        WHERE id = ?
+    "#,
+    )
+    .bind(job.entry_id)
+    .fetch_one(&state.pool)
+    .await?;
+
+    let keep = match options.rerun {
+        Some(RerunStep::Summary) => true,
+        Some(RerunStep::Transcription | RerunStep::Both) => false,
+        // a retry after a failed summary doesn't need to transcribe again,
+        // entries transcribed before the language was detected were always transcribed as english
+        None => {
+            !options.transcription.translate
+                || translation.is_some()
+                || language.as_deref().is_none_or(|l| l == "en")
+        }
+    };
+    let (transcript, language, translation) = match transcript {
+        Some(transcript) if keep => (
+            transcript,
+            language.unwrap_or_else(|| "en".to_string()),
+            translation,
+        ),
+        _ => {
+            let (progress, mut updates) = watch::channel(0);
+            let reporter = spawn({
+                let jobs = state.jobs.clone();
+                let job = job.clone();
+                async move {
+                    let start = Instant::now();
+                    while updates.changed().await.is_ok() {
+                        let progress = (*updates.borrow_and_update()).clamp(0, 100) as u8;
+                        // assumes the remaining audio takes as long per percent as the part already done
+                        let eta = (progress > 0).then(|| {
+                            (start.elapsed().as_secs_f64() * (100 - progress) as f64
+                                / progress as f64) as u32
+                        });
+                        if let Err(e) = jobs.set_progress(&job, progress, eta).await {
+                            eprintln!("failed to update progress of job {}: {}", job.id, e);
+                        }
+                    }
+                }
+            });
+            let transcript = state
+                .transcriber
+                .transcribe(
+                    file,
+                    options.transcription.clone(),
+                    Box::new(move |p| {
+                        let _ = progress.send(p);
+                    }),
+                )
+                .await;
+            reporter.abort();
+            let mut transcript = transcript?;
+            transcript.filter();
+            save_transcript(&state.pool, job.entry_id, &transcript).await?;
+            if options.rerun == Some(RerunStep::Both) {
+                // the new transcript is stored, a retry only has to summarize it
+                let options = JobOptions {
+                    rerun: Some(RerunStep::Summary),
+                    ..options.clone()
+                };
+                state.jobs.set_options(&job, &options).await?;
+            }
+            let translation = transcript.translation.as_deref().map(format_segments);
+            (transcript.text, transcript.language, translation)
+        }
+    };
+    if options.rerun == Some(RerunStep::Transcription) {
+        state.jobs.set_state(&job, JobState::Done).await?;
+        return Ok(());
+    }
+
+    state.jobs.set_state(&job, JobState::Summarizing).await?;
+    // a translated note is summarized from the translation so the notes end up in english
+    let (transcript, language) = match translation {
+        Some(translation) => (translation, "English"),
+        None => (
+            transcript,
+            get_lang_id(&language)
+                .and_then(get_lang_str_full)
+                .unwrap_or(&language),
+        ),
+    };
+    // a failed attempt's draft isn't continued
+    state.jobs.set_draft(&job, "").await?;
+    let (draft, mut updates) = watch::channel(String::new());
+    let reporter = spawn({
+        let jobs = state.jobs.clone();
+        let job = job.clone();
+        async move {
+            while updates.changed().await.is_ok() {
+                let text = updates.borrow_and_update().clone();
+                if let Err(e) = jobs.set_draft(&job, &text).await {
+                    eprintln!("failed to save the draft of job {}: {}", job.id, e);
+                }
+                sleep(DRAFT_INTERVAL).await;
+            }
+        }
+    });
+    let summary = sumarize(
+        &state.llm,
+        &options.summary,
+        transcript,
+        language,
+        options.transcription.diarize,
+        Box::new(move |text| {
+            draft.send_replace(text.to_string());
+        }),
+    )
+    .await;
+    reporter.abort();
+    save_summary(
+        &state.pool,
+        job.entry_id,
+        &summary?,
+        state.llm.model(&options.summary),
+    )
+    .await?;
+
+    state.jobs.set_state(&job, JobState::Done).await?;
+    Ok(())
+}
+
+/// Stores a summary on an entry, as markdown and in the tables of its parts,
+/// replacing the one of an earlier run.
+async fn save_summary(
+    pool: &Pool<Sqlite>,
+    entry_id: u32,
+    summary: &StructuredSummary,
+    model: &str,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    query(
+        r#"
+        UPDATE entries
+        SET summary = ?, title = ?, summary_model = ?, draft = NULL
+        WHERE id = ?
+    "#,
+    )
+    .bind(summary.to_markdown())
+    .bind(&summary.title)
+    .bind(model)
+    .bind(entry_id)
+    .execute(&mut *tx)
+    .await?;
+    for table in ["sections", "key_terms", "formulas", "open_questions"] {
+        query(&format!("DELETE FROM {} WHERE entry_id = ?", table))
+            .bind(entry_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    for (index, section) in summary.sections.iter().enumerate() {
+        query(
+            r#"
+            INSERT INTO sections (entry_id, "index", heading, points)
+            VALUES (?, ?, ?, ?)
+        "#,
+        )
+        .bind(entry_id)
+        .bind(index as u32)
+        .bind(&section.heading)
+        .bind(Json(&section.points))
+        .execute(&mut *tx)
+        .await?;
+    }
+    for (index, term) in summary.key_terms.iter().enumerate() {
+        query(
+            r#"
+            INSERT INTO key_terms (entry_id, "index", term, definition)
+            VALUES (?, ?, ?, ?)
+        "#,
+        )
+        .bind(entry_id)
+        .bind(index as u32)
+        .bind(&term.term)
+        .bind(&term.definition)
+        .execute(&mut *tx)
+        .await?;
+    }
+    for (index, formula) in summary.formulas.iter().enumerate() {
+        query(
+            r#"
+            INSERT INTO formulas (entry_id, "index", formula, description)
+            VALUES (?, ?, ?, ?)
+        "#,
+        )
+        .bind(entry_id)
+        .bind(index as u32)
+        .bind(&formula.formula)
+        .bind(&formula.description)
+        .execute(&mut *tx)
+        .await?;
+    }
+    for (index, question) in summary.open_questions.iter().enumerate() {
+        query(
+            r#"
+            INSERT INTO open_questions (entry_id, "index", question)
+            VALUES (?, ?, ?)
+        "#,
+        )
+        .bind(entry_id)
+        .bind(index as u32)
+        .bind(question)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+/// Stores a transcript and its segments on an entry, replacing the ones of an earlier run.
+pub async fn save_transcript(
+    pool: &Pool<Sqlite>,
+    entry_id: u32,
+    transcript: &Transcript,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    query(
+        r#"
+        UPDATE entries
+        SET transcript = ?, language = ?, translation = ?, removed = ?
+        WHERE id = ?
+    "#,
+    )
+    .bind(&transcript.text)
+    .bind(&transcript.language)
+    .bind(transcript.translation.as_deref().map(format_segments))
+    .bind(Json(&transcript.removed))
+    .bind(entry_id)
+    .execute(&mut *tx)
+    .await?;
+    query(
+        r#"
+        DELETE FROM segments
+        WHERE entry_id = ?
+    "#,
+    )
+    .bind(entry_id)
+    .execute(&mut *tx)
+    .await?;
+    for segment in &transcript.segments {
+        query(
+            r#"
+            INSERT INTO segments (entry_id, "index", start_ms, end_ms, text, probability, speaker)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+        "#,
+        )
+        .bind(entry_id)
+        .bind(segment.index)
+        .bind(segment.start_ms)
+        .bind(segment.end_ms)
+        .bind(&segment.text)
+        .bind(segment.probability)
+        .bind(segment.speaker)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+    use crate::{
+        ai::Llm,
+        config::Config,
+        db,
+        transcription::{MockTranscriber, Transcriber},
+    };
+
+    /// A fresh in-memory database with the same tables as the server's. A single connection,
+    /// every connection to `:memory:` would get a database of its own.
+    async fn state() -> AppState {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        db::migrate(&pool).await.unwrap();
+        let mut llm = Llm::new(&Config::from_env());
+        // nothing listens there, summaries fail right away
+        llm.url = "http://127.0.0.1:9".to_string();
+        AppState {
+            jobs: JobQueue::new(pool.clone()),
+            pool,
+            transcriber: Arc::new(MockTranscriber),
+            llm,
+        }
+    }
+
+    async fn upload(state: &AppState, options: JobOptions) -> Job {
+        let id: u32 = query_scalar(
+            r#"
+            INSERT INTO entries (file, name)
+            VALUES ('lecture.wav', 'Lecture')
+            RETURNING id
+        "#,
+        )
+        .fetch_one(&state.pool)
+        .await
+        .unwrap();
+        state.jobs.enqueue(id, options).await.unwrap();
+        state.jobs.claim().await.unwrap().unwrap()
+    }
+
+    async fn job_state(state: &AppState, job: &Job) -> JobState {
+        query_scalar("SELECT state FROM jobs WHERE id = ?")
+            .bind(job.id)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn transcribes_a_note() {
+        let state = state().await;
+        let job = upload(
+            &state,
+            JobOptions {
+                rerun: Some(RerunStep::Transcription),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        process(state.clone(), job.clone()).await.unwrap();
+
+        let (transcript, language): (String, String) =
+            query_as("SELECT transcript, language FROM entries WHERE id = ?")
+                .bind(job.entry_id)
+                .fetch_one(&state.pool)
+                .await
+                .unwrap();
+        assert!(transcript.contains("Good morning"));
+        assert_eq!(language, "en");
+        let segments: u32 = query_scalar("SELECT COUNT(*) FROM segments WHERE entry_id = ?")
+            .bind(job.entry_id)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(segments, 3);
+        assert_eq!(job_state(&state, &job).await, JobState::Done);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_transcript_when_the_summary_fails() {
+        let state = state().await;
+        let job = upload(&state, JobOptions::default()).await;
+
+        assert!(process(state.clone(), job.clone()).await.is_err());
+        let transcript: Option<String> = query_scalar("SELECT transcript FROM entries WHERE id = ?")
+            .bind(job.entry_id)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert!(transcript.is_some());
+
+        // the retry waits, so the job can't be claimed again right away
+        state.jobs.fail(&job, "ollama is down".to_string()).await.unwrap();
+        assert_eq!(job_state(&state, &job).await, JobState::Queued);
+        assert!(state.jobs.claim().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn fails_for_good_after_the_last_attempt() {
+        let state = state().await;
+        let job = upload(&state, JobOptions::default()).await;
+
+        let job = Job {
+            attempts: MAX_ATTEMPTS,
+            ..job
+        };
+        state.jobs.fail(&job, "ollama is down".to_string()).await.unwrap();
+        assert_eq!(job_state(&state, &job).await, JobState::Failed);
+    }
+
+    #[tokio::test]
+    async fn enqueues_only_notes_without_an_unfinished_job() {
+        let state = state().await;
+        let job = upload(&state, JobOptions::default()).await;
+
+        let mut tx = state.pool.begin().await.unwrap();
+        let queued = JobQueue::enqueue_idle(&mut tx, job.entry_id, JobOptions::default());
+        assert!(!queued.await.unwrap());
+        tx.commit().await.unwrap();
+
+        let job = Job {
+            attempts: MAX_ATTEMPTS,
+            ..job
+        };
+        state.jobs.fail(&job, "ollama is down".to_string()).await.unwrap();
+        let mut tx = state.pool.begin().await.unwrap();
+        let queued = JobQueue::enqueue_idle(&mut tx, job.entry_id, JobOptions::default());
+        assert!(queued.await.unwrap());
+        // the job added in this transaction counts as well
+        let queued = JobQueue::enqueue_idle(&mut tx, job.entry_id, JobOptions::default());
+        assert!(!queued.await.unwrap());
+        tx.commit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn recover_fails_jobs_interrupted_too_often() {
+        let state = state().await;
+        let job = upload(&state, JobOptions::default()).await;
+        state.jobs.recover().await.unwrap();
+        assert_eq!(job_state(&state, &job).await, JobState::Queued);
+
+        for _ in 1..MAX_ATTEMPTS {
+            state.jobs.claim().await.unwrap().unwrap();
+            state.jobs.recover().await.unwrap();
+        }
+        assert_eq!(job_state(&state, &job).await, JobState::Failed);
+        let error: Option<String> = query_scalar("SELECT error FROM jobs WHERE id = ?")
+            .bind(job.id)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(error.as_deref(), Some("interrupted too often"));
+    }
+
+    #[tokio::test]
+    async fn retries_a_failed_job_in_place() {
+        let state = state().await;
+        let job = upload(&state, JobOptions::default()).await;
+        let job = Job {
+            attempts: MAX_ATTEMPTS,
+            ..job
+        };
+        state.jobs.fail(&job, "ollama is down".to_string()).await.unwrap();
+
+        assert!(state.jobs.retry(job.entry_id).await.unwrap());
+        let retried = state.jobs.claim().await.unwrap().unwrap();
+        assert_eq!(retried.id, job.id);
+        assert_eq!(retried.attempts, 1);
+        let versions: u32 = query_scalar("SELECT COUNT(*) FROM versions")
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(versions, 0);
+        // a job that is running can't be retried
+        assert!(!state.jobs.retry(job.entry_id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn save_transcript_replaces_the_earlier_segments() {
+        let state = state().await;
+        let job = upload(&state, JobOptions::default()).await;
+        let transcript = MockTranscriber
+            .transcribe(String::new(), TranscriptionOptions::default(), Box::new(|_| {}))
+            .await
+            .unwrap();
+        save_transcript(&state.pool, job.entry_id, &transcript).await.unwrap();
+
+        let shorter = Transcript {
+            text: "Only this.".to_string(),
+            segments: transcript.segments[..1].to_vec(),
+            language: "de".to_string(),
+            translation: Some(transcript.segments[..1].to_vec()),
+            removed: Vec::new(),
+        };
+        save_transcript(&state.pool, job.entry_id, &shorter).await.unwrap();
+
+        let (text, language, translation): (String, String, Option<String>) =
+            query_as("SELECT transcript, language, translation FROM entries WHERE id = ?")
+                .bind(job.entry_id)
+                .fetch_one(&state.pool)
+                .await
+                .unwrap();
+        assert_eq!(text, "Only this.");
+        assert_eq!(language, "de");
+        assert_eq!(translation, Some(format_segments(&shorter.segments)));
+        let segments: u32 = query_scalar("SELECT COUNT(*) FROM segments WHERE entry_id = ?")
+            .bind(job.entry_id)
+            .fetch_one(&state.pool)
+            .await
+            .unwrap();
+        assert_eq!(segments, 1);
+    }
+}
@@ -1,7 +1,31 @@
+#[cfg(feature = "ssr")]
+pub mod ai;
 pub mod app;
+#[cfg(feature = "ssr")]
+pub mod audio;
+#[cfg(feature = "ssr")]
+pub mod config;
+pub mod courses;
 pub mod datatypes;
+#[cfg(feature = "ssr")]
+pub mod db;
+#[cfg(feature = "ssr")]
+pub mod diarize;
+#[cfg(feature = "ssr")]
+pub mod jobs;
+#[cfg(feature = "ssr")]
+pub mod markdown;
+#[cfg(feature = "ssr")]
+pub mod models;
 pub mod pages;
+#[cfg(feature = "ssr")]
+pub mod preprocess;
 pub mod query;
+pub mod rerun;
+#[cfg(feature = "ssr")]
+pub mod transcription;
+#[cfg(feature = "ssr")]
+pub mod vad;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
@@ -0,0 +1,290 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Query, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, Row};
+use tokio::{fs, spawn, sync::mpsc, task::spawn_blocking};
+use uuid::Uuid;
+use whisper_rs::{get_lang_id, get_lang_str};
+
+use crate::{
+    audio::encode_wav,
+    datatypes::{AppState, RerunStep, Segment},
+    jobs::{save_transcript, JobOptions},
+    transcription::{format_segments, Transcript, TranscriptionOptions},
+};
+
+/// Whisper runs again once this much new audio arrived, 3 seconds.
+const STEP: usize = 3 * 16000;
+/// Segments ending this close to the end of the audio may still change with the next words.
+const HOLD_MS: u32 = 2000;
+/// Audio older than this is committed even if whisper didn't end a segment, 25 seconds.
+const MAX_WINDOW: usize = 25 * 16000;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Deserialize)]
+pub struct LiveParams {
+    /// name of the note, defaults to `Live recording`
+    name: Option<String>,
+    /// whisper language code or name or `auto`, defaults to `auto`
+    language: Option<String>,
+}
+
+/// Sent to the client as json text messages.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum LiveEvent {
+    /// segments that won't change anymore
+    Final {
+        segments: Vec<Segment>,
+    },
+    /// the current guess for the audio after the last final segment, replaces the previous one
+    Partial {
+        segments: Vec<Segment>,
+    },
+    /// the recording is stored as a note and queued for its summary
+    Done {
+        id: u32,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Live transcription of a recording in progress. The client sends 16KHz mono
+/// signed 16 bit little endian PCM as binary messages and `stop` as a text message,
+/// or closes the socket, when the recording ends. Timestamps of the segments sent
+/// back are milliseconds since the start of the recording.
+pub async fn live(
+    ws: WebSocketUpgrade,
+    Query(params): Query<LiveParams>,
+    Extension(state): Extension<AppState>,
+) -> Response {
+    let language = match params.language.as_deref() {
+        None | Some("auto") => None,
+        Some(language) => match get_lang_id(language).and_then(get_lang_str) {
+            Some(language) => Some(language.to_string()),
+            None => return StatusCode::BAD_REQUEST.into_response(),
+        },
+    };
+    let name = params.name.unwrap_or_else(|| "Live recording".to_string());
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = session(socket, state, name, language).await {
+            eprintln!("live transcription failed: {}", e);
+        }
+    })
+}
+
+async fn session(
+    socket: WebSocket,
+    state: AppState,
+    name: String,
+    language: Option<String>,
+) -> Result<(), BoxError> {
+    let (mut sender, mut receiver) = socket.split();
+
+    // the socket is read on its own so whisper running never holds up the client
+    let (audio, mut chunks) = mpsc::unbounded_channel::<Vec<f32>>();
+    spawn(async move {
+        while let Some(Ok(message)) = receiver.next().await {
+            match message {
+                Message::Binary(bytes) => {
+                    let samples = bytes
+                        .chunks_exact(2)
+                        .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+                        .collect();
+                    if audio.send(samples).is_err() {
+                        break;
+                    }
+                }
+                Message::Text(text) if text.as_str() == "stop" => break,
+                Message::Close(_) => break,
+                _ => {}
+            }
+        }
+    });
+
+    let mut options = TranscriptionOptions {
+        language,
+        ..Default::default()
+    };
+    let mut recording: Vec<f32> = Vec::new();
+    let mut committed: Vec<Segment> = Vec::new();
+    let mut failed = None;
+    // where the audio that isn't covered by final segments starts
+    let mut start = 0;
+    let mut open = true;
+    while open {
+        let target = recording.len() + STEP;
+        while recording.len() < target {
+            match chunks.recv().await {
+                Some(chunk) => recording.extend(chunk),
+                None => {
+                    open = false;
+                    break;
+                }
+            }
+        }
+        // whatever else arrived while whisper was busy
+        while let Ok(chunk) = chunks.try_recv() {
+            recording.extend(chunk);
+        }
+        if start >= recording.len() {
+            continue;
+        }
+
+        let transcript = match state
+            .transcriber
+            .transcribe_samples(recording[start..].to_vec(), options.clone())
+            .await
+        {
+            Ok(transcript) => transcript,
+            Err(e) => {
+                // what was said so far is still saved and the job transcribes the whole recording again
+                failed = Some(e);
+                break;
+            }
+        };
+        // the first detected language sticks, switching mid lecture only causes gibberish
+        options.language.get_or_insert(transcript.language);
+
+        let offset = (start / 16) as u32;
+        let end = (recording.len() / 16) as u32;
+        let mut segments = transcript.segments;
+        for segment in &mut segments {
+            segment.start_ms += offset;
+            segment.end_ms += offset;
+        }
+        let mut settled = if open {
+            segments
+                .iter()
+                .take_while(|s| s.end_ms + HOLD_MS <= end)
+                .count()
+        } else {
+            segments.len()
+        };
+        let overflow = recording.len() - start > MAX_WINDOW;
+        let silence = segments.is_empty();
+        if overflow {
+            // whisper didn't find the end of a sentence in a long time, keep the window bounded anyway
+            // by committing all but the last segment, or the only one
+            settled = segments
+                .len()
+                .saturating_sub(1)
+                .max(settled)
+                .max(segments.len().min(1));
+        }
+        let partial = segments.split_off(settled);
+
+        if let Some(last) = segments.last() {
+            start = (last.end_ms as usize * 16).clamp(start, recording.len());
+        } else if overflow && silence {
+            // nothing but silence
+            start = recording.len() - STEP;
+        }
+        if !segments.is_empty() {
+            for segment in &mut segments {
+                segment.index = committed.len() as u32;
+                committed.push(segment.clone());
+            }
+            send(&mut sender, &LiveEvent::Final { segments }).await;
+        }
+        send(&mut sender, &LiveEvent::Partial { segments: partial }).await;
+    }
+
+    // the recording is kept no matter whether the client is still there or whisper failed
+    if recording.is_empty() {
+        return failed.map_or(Ok(()), |e| Err(e.into()));
+    }
+    let saved = save(
+        &state,
+        name,
+        recording,
+        committed,
+        options,
+        failed.is_some(),
+    )
+    .await;
+    if let Some(e) = &failed {
+        let message = e.to_string();
+        send(&mut sender, &LiveEvent::Error { message }).await;
+    }
+    match saved {
+        Ok(id) => send(&mut sender, &LiveEvent::Done { id }).await,
+        Err(e) => {
+            let message = e.to_string();
+            send(&mut sender, &LiveEvent::Error { message }).await;
+            return Err(e);
+        }
+    }
+    failed.map_or(Ok(()), |e| Err(e.into()))
+}
+
+/// Sends an event if the client is still listening. A client that ended the recording by
+/// closing the socket or dropped off the network can't be written to anymore, that's fine.
+async fn send(
+    sender: &mut (impl SinkExt<Message, Error = axum::Error> + Unpin),
+    event: &LiveEvent,
+) {
+    let Ok(text) = serde_json::to_string(event) else {
+        return;
+    };
+    let _ = sender.send(Message::Text(text.into())).await;
+}
+
+/// Stores the recording like an upload, with the transcript already done,
+/// so the job only has to summarize it. An `incomplete` transcript is redone by the job.
+async fn save(
+    state: &AppState,
+    name: String,
+    recording: Vec<f32>,
+    segments: Vec<Segment>,
+    options: TranscriptionOptions,
+    incomplete: bool,
+) -> Result<u32, BoxError> {
+    let file_name = format!("{}.wav", Uuid::new_v4());
+    let bytes = spawn_blocking(move || encode_wav(&recording)).await??;
+    fs::write(&file_name, bytes).await?;
+
+    let id: u32 = query(
+        r#"
+        INSERT INTO entries (file, name)
+        VALUES (?, ?)
+        RETURNING id
+    "#,
+    )
+    .bind(&file_name)
+    .bind(name)
+    .fetch_one(&state.pool)
+    .await?
+    .get("id");
+    let mut transcript = Transcript {
+        text: format_segments(&segments),
+        segments,
+        language: options.language.clone().unwrap_or_else(|| "en".to_string()),
+        translation: None,
+        removed: Vec::new(),
+    };
+    transcript.filter();
+    save_transcript(&state.pool, id, &transcript).await?;
+    state
+        .jobs
+        .enqueue(
+            id,
+            JobOptions {
+                transcription: options,
+                rerun: incomplete.then_some(RerunStep::Both),
+                ..Default::default()
+            },
+        )
+        .await?;
+    Ok(id)
+}
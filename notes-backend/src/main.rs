@@ -1,7 +1,21 @@
 pub mod app;
+mod audio;
+mod config;
+pub mod courses;
+mod db;
+mod diarize;
+mod events;
+mod export;
+mod jobs;
+mod live;
+mod markdown;
+mod models;
 pub mod pages;
+mod preprocess;
 pub mod transcription;
+mod vad;
 pub mod query;
+pub mod rerun;
 mod upload;
 mod ai;
 pub mod datatypes;
@@ -11,71 +25,72 @@ pub mod datatypes;
 #[tokio::main]
 async fn main() {
     use sqlx::{Pool, Sqlite, sqlite::SqliteConnectOptions};
+    use crate::ai::Llm;
+    use crate::config::Config;
     use crate::datatypes::AppState;
-    use crate::{app::*, upload::upload};
-    use axum::{extract::DefaultBodyLimit, routing::post, Extension, Router};
-    use futures_util::StreamExt;
+    use crate::jobs::{self, JobQueue};
+    use crate::transcription::{MockTranscriber, OpenAiTranscriber, Transcriber, WhisperTranscriber};
+    use crate::{app::*, db, events::events, export::export, live::live, upload::upload};
+    use axum::{
+        extract::DefaultBodyLimit,
+        routing::{get, post},
+        Extension, Router,
+    };
     use leptos::prelude::*;
     use leptos_axum::{generate_route_list, LeptosRoutes};
-    use reqwest::Client;
-    use sqlx::query;
     use whisper_rs::install_logging_hooks;
-    use std::{env, fs::exists, str::FromStr};
-    use tokio::fs::File;
-    use tokio::io::AsyncWriteExt;
+    use std::{env, str::FromStr, sync::Arc};
 
     install_logging_hooks();
 
-    if !exists("ggml-large-v3-q5_0.bin").unwrap() {
-        println!("Downloading https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-large-v3-q5_0.bin");
-        let res = Client::new()
-            .get("https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-large-v3-q5_0.bin")
-            .send()
-            .await
-            .unwrap();
-
-        let mut file = File::create("ggml-large-v3-q5_0.bin").await.unwrap();
-        let mut stream = res.bytes_stream();
-
-        while let Some(chunk) = stream.next().await {
-            file.write_all(&chunk.unwrap()).await.unwrap();
-        }
-
-        println!("Downloaded https://huggingface.co/ggerganov/whisper.cpp/resolve/main/ggml-large-v3-q5_0.bin");
-    }
-
     let conf = get_configuration(None).unwrap();
     let leptos_options = conf.leptos_options;
     let routes = generate_route_list(App);
 
     let pool: Pool<Sqlite> = Pool::connect_with(
-        SqliteConnectOptions::from_str("sqlite://db.sqlite".into())
+        SqliteConnectOptions::from_str("sqlite://db.sqlite")
             .unwrap()
             .create_if_missing(true),
     )
     .await
     .unwrap();
 
-    query(
-        r#"
-        CREATE TABLE IF NOT EXISTS entries (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            file TEXT NOT NULL,
-            name TEXT NOT NULL,
-            transcript TEXT,
-            summary TEXT
-        )
-    "#,
-    )
-    .execute(&pool)
-    .await
-    .unwrap();
+    db::migrate(&pool).await.unwrap();
+
+    let config = Config::from_env();
+    let transcriber: Arc<dyn Transcriber> = match config.transcriber.as_str() {
+        "whisper" => {
+            let whisper = WhisperTranscriber::new(config.clone());
+            // the default model is downloaded and loaded right away instead of delaying the first upload,
+            // if that fails every job tries again and fails with the reason until it works
+            if let Err(e) = whisper.load(&config.model).await {
+                eprintln!("failed to load whisper model {}: {}", config.model, e);
+            }
+            Arc::new(whisper)
+        }
+        "openai" => Arc::new(OpenAiTranscriber::new(&config)),
+        "mock" => Arc::new(MockTranscriber),
+        other => panic!("unknown TRANSCRIBER {}, expected whisper, openai or mock", other),
+    };
+
+    let jobs = JobQueue::new(pool.clone());
+    // anything left unfinished by the last run goes back into the queue
+    jobs.recover().await.unwrap();
 
-    let state = AppState { pool };
+    let state = AppState {
+        pool,
+        transcriber,
+        jobs,
+        llm: Llm::new(&config),
+    };
     let state_pass = state.clone();
+    tokio::spawn(jobs::run(state.clone(), config.jobs));
 
     let app = Router::new()
         .route("/upload", post(upload))
+        .route("/events", get(events))
+        .route("/export/{id}/{format}", get(export))
+        .route("/live", get(live))
         .leptos_routes_with_context(&leptos_options, routes, move || provide_context(state_pass.clone()), {
             let leptos_options = leptos_options.clone();
             move || shell(leptos_options.clone())
@@ -0,0 +1,13 @@
+use pulldown_cmark::{html, Options, Parser};
+
+/// Renders the markdown of a summary to html that is safe to put into a page,
+/// the model might have copied markup out of the transcript.
+pub fn render(markdown: &str) -> String {
+    let parser = Parser::new_ext(
+        markdown,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS,
+    );
+    let mut out = String::new();
+    html::push_html(&mut out, parser);
+    ammonia::clean(&out)
+}
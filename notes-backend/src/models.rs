@@ -0,0 +1,343 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use futures_util::StreamExt;
+use reqwest::{header::RANGE, Client, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::{fs, io::AsyncWriteExt, task::spawn_blocking};
+
+use crate::config::Config;
+
+const DOWNLOADS: &str = "https://huggingface.co/ggerganov/whisper.cpp/resolve/main";
+/// lists the files of the repository together with their checksums
+const TREE: &str = "https://huggingface.co/api/models/ggerganov/whisper.cpp/tree/main";
+
+/// The ggml models published by whisper.cpp, smallest to largest.
+/// `.en` models only understand english, `q5`/`q8` are quantized and need less memory.
+pub const MODELS: &[&str] = &[
+    "tiny",
+    "tiny.en",
+    "tiny-q5_1",
+    "tiny.en-q5_1",
+    "tiny-q8_0",
+    "base",
+    "base.en",
+    "base-q5_1",
+    "base.en-q5_1",
+    "base-q8_0",
+    "small",
+    "small.en",
+    "small-q5_1",
+    "small.en-q5_1",
+    "small-q8_0",
+    "medium",
+    "medium.en",
+    "medium-q5_0",
+    "medium.en-q5_0",
+    "medium-q8_0",
+    "large-v1",
+    "large-v2",
+    "large-v2-q5_0",
+    "large-v2-q8_0",
+    "large-v3",
+    "large-v3-q5_0",
+    "large-v3-turbo",
+    "large-v3-turbo-q5_0",
+    "large-v3-turbo-q8_0",
+];
+
+#[derive(Debug)]
+pub enum ModelError {
+    Unknown(String),
+    /// the model isn't downloaded and offline mode forbids fetching it
+    Missing(PathBuf),
+    /// the model has no stored checksum and offline mode forbids fetching one
+    Unverified(PathBuf),
+    Io(io::Error),
+    Download(reqwest::Error),
+    /// the download server answered with neither the file nor the requested part of it
+    Status(StatusCode),
+    Checksum {
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::Unknown(name) => write!(f, "unknown model {}", name),
+            ModelError::Missing(path) => {
+                write!(f, "{} is missing and offline mode is on", path.display())
+            }
+            ModelError::Unverified(path) => write!(
+                f,
+                "{} can't be verified while offline mode is on, start once without it",
+                path.display()
+            ),
+            ModelError::Io(e) => write!(f, "failed to store model: {}", e),
+            ModelError::Download(e) => write!(f, "failed to download model: {}", e),
+            ModelError::Status(status) => {
+                write!(f, "failed to download model: unexpected response {}", status)
+            }
+            ModelError::Checksum { expected, actual } => write!(
+                f,
+                "model checksum mismatch, expected {} got {}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {}
+
+impl From<io::Error> for ModelError {
+    fn from(e: io::Error) -> Self {
+        ModelError::Io(e)
+    }
+}
+
+impl From<reqwest::Error> for ModelError {
+    fn from(e: reqwest::Error) -> Self {
+        ModelError::Download(e)
+    }
+}
+
+pub fn file_name(model: &str) -> String {
+    format!("ggml-{}.bin", model)
+}
+
+/// Returns the path of a model, downloading it first unless it's already in the models directory.
+/// Downloads go to a `.part` file that is only renamed once its checksum is verified,
+/// so every model file in the directory is complete. Files already there are verified as well,
+/// they may have been cut short by an older version that wrote them in place.
+pub async fn ensure(config: &Config, model: &str) -> Result<PathBuf, ModelError> {
+    if !MODELS.contains(&model) {
+        return Err(ModelError::Unknown(model.to_string()));
+    }
+    let path = config.models_dir.join(file_name(model));
+    let client = Client::new();
+    if fs::try_exists(&path).await? {
+        match verify(&client, config, model, &path).await {
+            Ok(()) => return Ok(path),
+            Err(ModelError::Checksum { .. }) if !config.offline => {
+                println!("{} is corrupt, downloading it again", path.display());
+                fs::remove_file(&path).await?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    if config.offline {
+        return Err(ModelError::Missing(path));
+    }
+
+    fs::create_dir_all(&config.models_dir).await?;
+    let expected = checksum(&client, model).await?;
+    let part = path.with_extension("bin.part");
+    let url = format!("{}/{}", DOWNLOADS, file_name(model));
+    println!("Downloading {}", url);
+    download(&client, &url, &part).await?;
+
+    let actual = hash(&part).await?;
+    if actual != expected {
+        // starting over is the only way out of a corrupt download
+        fs::remove_file(&part).await?;
+        return Err(ModelError::Checksum { expected, actual });
+    }
+    fs::rename(&part, &path).await?;
+    record(&path, &expected).await?;
+    println!("Downloaded {}", url);
+    Ok(path)
+}
+
+/// Checks a model file against the checksum stored next to it, which lets offline mode verify
+/// models too. Files without one, from before checksums were stored, are checked against
+/// Hugging Face's and get it stored once they pass. Hashing a large model takes a while, so
+/// the size and modification time are stored with the checksum and the file is only hashed
+/// again once they change.
+async fn verify(
+    client: &Client,
+    config: &Config,
+    model: &str,
+    path: &Path,
+) -> Result<(), ModelError> {
+    let (expected, recorded) = match fs::read_to_string(checksum_path(path)).await {
+        Ok(stored) => {
+            let mut lines = stored.lines();
+            let expected = lines.next().unwrap_or_default().trim().to_string();
+            (expected, lines.next().map(|stamp| stamp.trim().to_string()))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if config.offline {
+                return Err(ModelError::Unverified(path.to_path_buf()));
+            }
+            (checksum(client, model).await?, None)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if recorded.is_some() && recorded == stamp(path).await? {
+        return Ok(());
+    }
+    let actual = hash(path).await?;
+    if actual != expected {
+        return Err(ModelError::Checksum { expected, actual });
+    }
+    record(path, &expected).await
+}
+
+/// Stores the checksum of a verified model next to it, together with its stamp.
+async fn record(path: &Path, checksum: &str) -> Result<(), ModelError> {
+    let stored = match stamp(path).await? {
+        Some(stamp) => format!("{}\n{}\n", checksum, stamp),
+        None => format!("{}\n", checksum),
+    };
+    fs::write(checksum_path(path), stored).await?;
+    Ok(())
+}
+
+/// Size and modification time of a file, `None` where the file system has no modification time.
+async fn stamp(path: &Path) -> Result<Option<String>, ModelError> {
+    let metadata = fs::metadata(path).await?;
+    Ok(metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| format!("{} {}", metadata.len(), modified.as_nanos())))
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    path.with_extension("bin.sha256")
+}
+
+#[derive(Deserialize)]
+struct TreeEntry {
+    path: String,
+    lfs: Option<Lfs>,
+}
+
+#[derive(Deserialize)]
+struct Lfs {
+    /// sha256 of the file
+    oid: String,
+}
+
+/// Hugging Face stores models with git lfs, whose object id is the sha256 of the file.
+async fn checksum(client: &Client, model: &str) -> Result<String, ModelError> {
+    let tree: Vec<TreeEntry> = client
+        .get(TREE)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let file_name = file_name(model);
+    tree.into_iter()
+        .find(|entry| entry.path == file_name)
+        .and_then(|entry| entry.lfs)
+        .map(|lfs| lfs.oid)
+        .ok_or(ModelError::Unknown(model.to_string()))
+}
+
+/// Appends to `part`, continuing where an interrupted download stopped.
+async fn download(client: &Client, url: &str, part: &Path) -> Result<(), ModelError> {
+    let done = match fs::metadata(part).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+    let res = client
+        .get(url)
+        .header(RANGE, format!("bytes={}-", done))
+        .send()
+        .await?;
+    // the part is already the whole file
+    if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Ok(());
+    }
+    // a failed request keeps the part, the next attempt resumes it
+    let res = res.error_for_status()?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(part)
+        .await?;
+    match res.status() {
+        StatusCode::PARTIAL_CONTENT => println!("Resuming at {} MB", done / 1_000_000),
+        // the server ignored the range, start from the beginning
+        StatusCode::OK => file.set_len(0).await?,
+        status => return Err(ModelError::Status(status)),
+    }
+
+    let mut stream = res.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        file.write_all(&chunk?).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// Hashes a file off the async threads, models are gigabytes.
+async fn hash(path: &Path) -> Result<String, ModelError> {
+    let path = path.to_path_buf();
+    Ok(spawn_blocking(move || sha256(&path))
+        .await
+        .map_err(io::Error::other)??)
+}
+
+fn sha256(path: &Path) -> Result<String, io::Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(format!("{:x}", hasher.finalize()));
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn hashes_a_model_again_only_once_it_changed() {
+        let dir = std::env::temp_dir().join(format!("models-{}", std::process::id()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join(file_name("tiny"));
+        fs::write(&path, b"weights").await.unwrap();
+        let config = Config {
+            offline: true,
+            ..Config::from_env()
+        };
+        let client = Client::new();
+
+        // a checksum copied in by hand, without a stamp, is checked once and gets one
+        fs::write(checksum_path(&path), format!("{}\n", hash(&path).await.unwrap()))
+            .await
+            .unwrap();
+        verify(&client, &config, "tiny", &path).await.unwrap();
+        let stored = fs::read_to_string(checksum_path(&path)).await.unwrap();
+        assert_eq!(stored.lines().count(), 2);
+
+        // with a matching stamp the file isn't hashed, a wrong checksum goes unnoticed
+        let stamp = stored.lines().nth(1).unwrap();
+        fs::write(checksum_path(&path), format!("bogus\n{}\n", stamp))
+            .await
+            .unwrap();
+        verify(&client, &config, "tiny", &path).await.unwrap();
+
+        // once the file changes it is hashed again
+        fs::write(&path, b"other weights").await.unwrap();
+        let result = verify(&client, &config, "tiny", &path).await;
+        assert!(matches!(result, Err(ModelError::Checksum { .. })));
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+use crate::courses::{courses, AddCourse, DeleteCourse, SaveCourse};
+use leptos::{component, form::ActionForm, prelude::*, view, IntoView};
+
+#[component]
+pub fn CoursesPage() -> impl IntoView {
+    let add = ServerAction::<AddCourse>::new();
+    let save = ServerAction::<SaveCourse>::new();
+    let delete = ServerAction::<DeleteCourse>::new();
+    let data = Resource::new(
+        move || (add.version().get(), save.version().get(), delete.version().get()),
+        |_| async move { courses().await.ok() },
+    );
+
+    view! {
+        <ActionForm action=add attr:class="grid grid-cols-[1fr_10em] gap-2 p-3 bg-gray-800 text-gray-200 m-1">
+            <input name="name" required placeholder="New course" class="p-1 bg-gray-700" />
+            <button type="submit" class="text-left">Add course</button>
+        </ActionForm>
+        <Suspense fallback=|| {
+            view! { <h1>Loading...</h1> }
+        }>
+            {move || {
+                data.get()
+                    .map(|items| {
+                        view! {
+                            <ul>
+                                {items
+                                    .map(|c| {
+                                        c.into_iter()
+                                            .map(|c| {
+                                                view! {
+                                                    <li class="grid grid-cols-[1fr_10em] p-3 bg-gray-800 text-gray-200 m-1">
+                                                        <ActionForm action=save attr:class="grid grid-cols-[15em_1fr_10em] gap-2">
+                                                            <input type="hidden" name="id" value=c.id />
+                                                            <input name="name" required value=c.name class="p-1 bg-gray-700 h-8" />
+                                                            <textarea
+                                                                name="vocabulary"
+                                                                rows="3"
+                                                                placeholder="eigenvector, Prof. Smith, NumPy"
+                                                                class="p-1 bg-gray-700"
+                                                            >
+                                                                {c.vocabulary}
+                                                            </textarea>
+                                                            <button type="submit" class="leading-8 h-8 text-left">Save</button>
+                                                        </ActionForm>
+                                                        <ActionForm action=delete>
+                                                            <input type="hidden" name="id" value=c.id />
+                                                            <button type="submit" class="leading-8 h-8 text-left">Delete</button>
+                                                        </ActionForm>
+                                                    </li>
+                                                }
+                                            })
+                                            .collect::<Vec<_>>()
+                                    })}
+                            </ul>
+                        }
+                    })
+            }}
+        </Suspense>
+    }
+}
@@ -1,10 +1,55 @@
-use crate::query::query;
+use std::collections::HashMap;
+
+use crate::{
+    datatypes::{JobState, Note, Progress, RerunStep},
+    query::query,
+    rerun::{Rerun, Retry},
+};
+#[cfg(not(feature = "ssr"))]
+use crate::datatypes::JobEvent;
+#[cfg(not(feature = "ssr"))]
+use codee::string::JsonSerdeCodec;
 use leptos::{component, prelude::*, view, IntoView};
-use leptos_use::{use_clipboard, UseClipboardReturn};
+#[cfg(not(feature = "ssr"))]
+use leptos_use::{use_clipboard, use_event_source, UseClipboardReturn, UseEventSourceReturn};
 
 #[component]
 pub fn HomePage() -> impl IntoView {
-    let data = Resource::new(|| (), |_| async move { query().await.ok() });
+    let rerun = ServerAction::<Rerun>::new();
+    let retry = ServerAction::<Retry>::new();
+    let data = Resource::new(
+        move || (rerun.version().get(), retry.version().get()),
+        |_| async move { query().await.ok() },
+    );
+    // the latest progress of every note, pushed by the server while it works on them
+    let live = RwSignal::new(HashMap::<u32, Progress>::new());
+    // the summary written so far of every note that is being summarized
+    let drafts = RwSignal::new(HashMap::<u32, String>::new());
+
+    #[cfg(not(feature = "ssr"))]
+    {
+        let UseEventSourceReturn { data: event, .. } =
+            use_event_source::<JobEvent, JsonSerdeCodec>("/events");
+        Effect::new(move || match event.get() {
+            Some(JobEvent::Progress(event)) => {
+                let known = data
+                    .get_untracked()
+                    .flatten()
+                    .is_some_and(|notes| notes.iter().any(|n| n.id == event.id));
+                // new uploads and finished notes need the full row from the server
+                if !known || matches!(event.state, JobState::Done | JobState::Failed) {
+                    data.refetch();
+                }
+                live.update(|live| {
+                    live.insert(event.id, event);
+                });
+            }
+            Some(JobEvent::Draft(draft)) => drafts.update(|drafts| {
+                drafts.insert(draft.id, draft.text);
+            }),
+            None => {}
+        });
+    }
 
     #[cfg(feature = "ssr")]
     let copy = |_data: &str| {};
@@ -16,6 +61,8 @@ pub fn HomePage() -> impl IntoView {
         copied: _,
         copy,
     } = use_clipboard();
+    // `Callback` is `Copy`, so every button can take it without cloning the clipboard's closure
+    let copy = Callback::new(move |text: String| copy(&text));
 
     view! {
         <Suspense fallback=|| {
@@ -30,27 +77,102 @@ pub fn HomePage() -> impl IntoView {
                                     .map(|n| {
                                         n.into_iter()
                                             .map(|n| {
-                                                if n.summary.is_some() {
+                                                // a note that is being redone shows its progress instead of the old output
+                                                let busy = matches!(
+                                                    n.state,
+                                                    Some(JobState::Queued | JobState::Transcribing | JobState::Summarizing)
+                                                );
+                                                if n.summary.is_some() && !busy {
+                                                    let id = n.id;
+                                                    let redo = move |step| {
+                                                        rerun.dispatch(Rerun {
+                                                            id,
+                                                            step,
+                                                            language: None,
+                                                            model: None,
+                                                            vocabulary: None,
+                                                            summary_model: None,
+                                                        });
+                                                    };
                                                     view! {
-                                                        <li class="grid grid-cols-[1fr_10em_10em] p-3 bg-gray-800 text-gray-200 h-14 m-1">
-                                                                <p class="leading-8 h-8">{n.name.clone()}</p>
-                                                                <button class="leading-8 h-8 text-left" on:click={
-                                                                    let copy = copy.clone();
-                                                                    move |_| copy(&n.transcript.clone().unwrap())
-                                                                }>Transcript</button>
-                                                                <button class="leading-8 h-8 text-left" on:click={
-                                                                    let copy = copy.clone();
-                                                                    move |_| copy(&n.summary.clone().unwrap())
-                                                                }>Summary</button>
+                                                        <li class=if n.translation.is_some() {
+                                                            "grid grid-cols-[1fr_10em_10em_10em_8em_16em] p-3 bg-gray-800 text-gray-200 h-14 m-1"
+                                                        } else {
+                                                            "grid grid-cols-[1fr_10em_10em_8em_16em] p-3 bg-gray-800 text-gray-200 h-14 m-1"
+                                                        }>
+                                                                <p class="leading-8 h-8">
+                                                                    <a class="underline" href=format!("/notes/{}", n.id)>{n.name.clone()}</a>
+                                                                    <span class="ml-2 text-sm text-gray-400 uppercase">{n.language.clone()}</span>
+                                                                    // the hallucination filter's report, hovering lists what it took out
+                                                                    {(!n.removed.is_empty()).then(|| view! {
+                                                                        <span class="ml-2 text-sm text-gray-400" title=removed_details(&n)>
+                                                                            {format!("{} removed", n.removed.len())}
+                                                                        </span>
+                                                                    })}
+                                                                </p>
+                                                                <button class="leading-8 h-8 text-left" on:click=move |_| copy.run(n.transcript.clone().unwrap())>
+                                                                    Transcript
+                                                                </button>
+                                                                {n.translation.clone().map(|translation| {
+                                                                    view! {
+                                                                        <button class="leading-8 h-8 text-left" on:click=move |_| copy.run(translation.clone())>
+                                                                            Translation
+                                                                        </button>
+                                                                    }
+                                                                })}
+                                                                <button
+                                                                    class="leading-8 h-8 text-left"
+                                                                    title=n.summary_model.clone().map(|model| format!("Written by {}", model))
+                                                                    on:click=move |_| copy.run(n.summary.clone().unwrap())
+                                                                >Summary</button>
+                                                                // notes transcribed before segments were stored have no timestamps to export
+                                                                <p class="leading-8 h-8">
+                                                                    {(!n.segments.is_empty()).then(|| view! {
+                                                                        <a class="mr-3 underline" href=format!("/export/{}/srt", n.id) download>SRT</a>
+                                                                        <a class="mr-3 underline" href=format!("/export/{}/vtt", n.id) download>VTT</a>
+                                                                    })}
+                                                                    {n.title.is_some().then(|| view! {
+                                                                        <a class="underline" href=format!("/export/{}/md", n.id) download>MD</a>
+                                                                    })}
+                                                                </p>
+                                                                // the old output is kept as a version
+                                                                <p class="leading-8 h-8 text-gray-400">
+                                                                    "Redo "
+                                                                    <button class="underline" on:click=move |_| redo(RerunStep::Transcription)>transcript</button>
+                                                                    ", "
+                                                                    <button class="underline" on:click=move |_| redo(RerunStep::Summary)>summary</button>
+                                                                    " or "
+                                                                    <button class="underline" on:click=move |_| redo(RerunStep::Both)>both</button>
+                                                                    <a class="ml-2 underline" href=format!("/notes/{}#redo", id) title="Redo with other options">"..."</a>
+                                                                </p>
                                                         </li>
                                                     }
+                                                        .into_any()
                                                 } else {
+                                                    let draft = n.draft.clone();
+                                                    let id = n.id;
                                                     view! {
-                                                        <li class="grid grid-cols-[1fr_20em] p-3 bg-gray-800 text-gray-200 h-14 m-1">
-                                                                <p class="leading-8 h-8">{n.name.clone()}</p>
-                                                                <p class="leading-8 h-8">Transcribing...</p>
+                                                        <li class="grid grid-cols-[1fr_20em] p-3 bg-gray-800 text-gray-200 min-h-14 m-1">
+                                                                <p class="leading-8 h-8">
+                                                                    {n.name.clone()}
+                                                                    <span class="ml-2 text-sm text-gray-400 uppercase">{n.language.clone()}</span>
+                                                                </p>
+                                                                <Status note=n live=live retry=retry />
+                                                                // the notes appear while the model writes them
+                                                                {move || {
+                                                                    drafts
+                                                                        .with(|drafts| drafts.get(&id).cloned())
+                                                                        .or(draft.clone())
+                                                                        .filter(|text| !text.is_empty())
+                                                                        .map(|text| view! {
+                                                                            <pre class="col-span-2 mt-2 max-h-64 overflow-y-auto whitespace-pre-wrap text-sm text-gray-300">
+                                                                                {text}
+                                                                            </pre>
+                                                                        })
+                                                                }}
                                                         </li>
                                                     }
+                                                        .into_any()
                                                 }
                                             })
                                             .collect::<Vec<_>>()
@@ -62,3 +184,77 @@ pub fn HomePage() -> impl IntoView {
         </Suspense>
     }
 }
+
+#[component]
+fn Status(
+    note: Note,
+    live: RwSignal<HashMap<u32, Progress>>,
+    retry: ServerAction<Retry>,
+) -> impl IntoView {
+    let initial = Progress {
+        id: note.id,
+        state: note.state.unwrap_or(JobState::Queued),
+        progress: note.progress,
+        eta: note.eta,
+    };
+    let current = move || live.with(|live| live.get(&note.id).cloned()).unwrap_or(initial.clone());
+    let id = note.id;
+    let retry = move |_| {
+        retry.dispatch(Retry { id });
+    };
+
+    view! {
+        <div class="h-8">
+            {move || {
+                let current = current();
+                let text = match (current.state, current.progress) {
+                    (JobState::Failed, _) => format!("Failed: {}", note.error.clone().unwrap_or_default()),
+                    (JobState::Transcribing, Some(progress)) => match current.eta {
+                        Some(eta) => format!("Transcribing {}% · {} left", progress, format_eta(eta)),
+                        None => format!("Transcribing {}%", progress),
+                    },
+                    (state, _) => state.label().to_string(),
+                };
+                view! {
+                    <p class="leading-6 h-6 truncate" title=text.clone()>
+                        {(current.state == JobState::Failed).then(|| view! {
+                            <button class="mr-2 underline" on:click=retry>Retry</button>
+                        })}
+                        {text.clone()}
+                    </p>
+                    <div class="h-1 bg-gray-700">
+                        <div
+                            class="h-1 bg-blue-500 transition-all"
+                            style=format!("width: {}%", current.progress.unwrap_or(0))
+                        ></div>
+                    </div>
+                }
+            }}
+        </div>
+    }
+}
+
+fn removed_details(note: &Note) -> String {
+    note.removed
+        .iter()
+        .map(|r| {
+            format!(
+                "{}:{:02} {}{}: {}",
+                r.start_ms / 60000,
+                r.start_ms / 1000 % 60,
+                r.reason.label(),
+                if r.translation { " in translation" } else { "" },
+                r.text
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_eta(seconds: u32) -> String {
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{} min", seconds.div_ceil(60)),
+        _ => format!("{}h {} min", seconds / 3600, seconds % 3600 / 60),
+    }
+}
@@ -1,3 +1,7 @@
+pub mod courses;
 pub mod home;
+pub mod note;
 
+pub use courses::*;
 pub use home::*;
+pub use note::*;
@@ -0,0 +1,189 @@
+use crate::{
+    datatypes::{StructuredSummary, Version},
+    query::note_summary,
+    rerun::{versions, Rerun},
+};
+use leptos::{component, form::ActionForm, prelude::*, view, IntoView};
+use leptos_router::hooks::use_params_map;
+
+#[component]
+pub fn NotePage() -> impl IntoView {
+    let params = use_params_map();
+    let rerun = ServerAction::<Rerun>::new();
+    let id = move || {
+        params
+            .read()
+            .get("id")
+            .and_then(|id| id.parse::<u32>().ok())
+    };
+    let data = Resource::new(
+        move || (id(), rerun.version().get()),
+        |(id, _)| async move {
+            match id {
+                Some(id) => note_summary(id).await.ok(),
+                None => None,
+            }
+        },
+    );
+    let earlier = Resource::new(
+        move || (id(), rerun.version().get()),
+        |(id, _)| async move {
+            match id {
+                Some(id) => versions(id).await.ok(),
+                None => None,
+            }
+        },
+    );
+
+    view! {
+        <Suspense fallback=|| {
+            view! { <h1>Loading...</h1> }
+        }>
+            {move || {
+                data.get()
+                    .map(|summary| match summary {
+                        Some(summary) => {
+                            view! {
+                                <article class="p-3 m-1 bg-gray-800 text-gray-200">
+                                    <p class="text-sm text-gray-400">
+                                        {summary.name}
+                                        {summary.model.map(|model| format!(", written by {}", model))}
+                                    </p>
+                                    // notes summarized before summaries were structured only have their markdown
+                                    {match (summary.structured, summary.html) {
+                                        (Some(structured), _) => view! { <Structured summary=structured /> }.into_any(),
+                                        (None, Some(html)) => view! { <div class="summary" inner_html=html></div> }.into_any(),
+                                        (None, None) => view! { <p>Not summarized yet.</p> }.into_any(),
+                                    }}
+                                </article>
+                                {id().map(|id| view! { <Redo id=id rerun=rerun /> })}
+                            }
+                                .into_any()
+                        }
+                        None => view! { <h1 class="p-3 text-gray-200">Note not found.</h1> }.into_any(),
+                    })
+            }}
+        </Suspense>
+        <Suspense>
+            {move || {
+                earlier
+                    .get()
+                    .flatten()
+                    .map(|versions| {
+                        versions.into_iter().map(|version| view! { <Earlier version=version /> }).collect::<Vec<_>>()
+                    })
+            }}
+        </Suspense>
+    }
+}
+
+/// Redoes the note with other options, empty fields keep the ones of the last run.
+#[component]
+fn Redo(id: u32, rerun: ServerAction<Rerun>) -> impl IntoView {
+    view! {
+        <ActionForm action=rerun attr:id="redo" attr:class="grid grid-cols-[10em_8em_12em_1fr_12em_8em] gap-2 p-3 m-1 bg-gray-800 text-gray-200">
+            <input type="hidden" name="id" value=id />
+            <select name="step" class="p-1 bg-gray-700 h-8">
+                <option value="both">Redo both</option>
+                <option value="transcription">Redo transcript</option>
+                <option value="summary">Redo summary</option>
+            </select>
+            <input name="language" placeholder="Language" title="e.g. en, de or auto" class="p-1 bg-gray-700 h-8" />
+            <input name="model" placeholder="Whisper model" title="e.g. small.en, default for the configured one" class="p-1 bg-gray-700 h-8" />
+            <textarea name="vocabulary" rows="1" placeholder="eigenvector, Prof. Smith, NumPy" class="p-1 bg-gray-700"></textarea>
+            <input name="summary_model" placeholder="Summary model" title="Ollama model, default for the configured one" class="p-1 bg-gray-700 h-8" />
+            <button type="submit" class="leading-8 h-8 text-left">Redo</button>
+        </ActionForm>
+        {move || {
+            rerun
+                .value()
+                .get()
+                .and_then(Result::err)
+                .map(|error| view! { <p class="p-3 m-1 text-red-400">{error.to_string()}</p> })
+        }}
+    }
+}
+
+/// An earlier output of the note, folded away below the current one.
+#[component]
+fn Earlier(version: Version) -> impl IntoView {
+    let details = [
+        version.language.map(|language| language.to_uppercase()),
+        version.summary_model.map(|model| format!("written by {}", model)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(", ");
+
+    view! {
+        <details class="p-3 m-1 bg-gray-800 text-gray-200">
+            <summary class="text-sm text-gray-400">
+                {format!("Version of {} UTC", version.created_at)}
+                {(!details.is_empty()).then(|| format!(", {}", details))}
+            </summary>
+            {match (version.structured, version.summary) {
+                (Some(structured), _) => view! { <Structured summary=structured /> }.into_any(),
+                (None, Some(summary)) => view! { <pre class="whitespace-pre-wrap">{summary}</pre> }.into_any(),
+                (None, None) => view! { <p>Not summarized.</p> }.into_any(),
+            }}
+            {version.transcript.map(|transcript| view! {
+                <h2 class="mt-3 text-gray-400">Transcript</h2>
+                <p class="whitespace-pre-wrap">{transcript}</p>
+            })}
+        </details>
+    }
+}
+
+#[component]
+fn Structured(summary: StructuredSummary) -> impl IntoView {
+    view! {
+        <div class="summary">
+            <h1>{summary.title}</h1>
+            {summary
+                .sections
+                .into_iter()
+                .map(|section| {
+                    view! {
+                        <h2>{section.heading}</h2>
+                        <ul>
+                            {section.points.into_iter().map(|point| view! { <li>{point}</li> }).collect::<Vec<_>>()}
+                        </ul>
+                    }
+                })
+                .collect::<Vec<_>>()}
+            {(!summary.key_terms.is_empty()).then(|| view! {
+                <h2>Key terms</h2>
+                <dl>
+                    {summary
+                        .key_terms
+                        .into_iter()
+                        .map(|term| view! {
+                            <dt>{term.term}</dt>
+                            <dd>{term.definition}</dd>
+                        })
+                        .collect::<Vec<_>>()}
+                </dl>
+            })}
+            {(!summary.formulas.is_empty()).then(|| view! {
+                <h2>Formulas</h2>
+                <dl>
+                    {summary
+                        .formulas
+                        .into_iter()
+                        .map(|formula| view! {
+                            <dt><code>{formula.formula}</code></dt>
+                            <dd>{formula.description}</dd>
+                        })
+                        .collect::<Vec<_>>()}
+                </dl>
+            })}
+            {(!summary.open_questions.is_empty()).then(|| view! {
+                <h2>Open questions</h2>
+                <ul>
+                    {summary.open_questions.into_iter().map(|question| view! { <li>{question}</li> }).collect::<Vec<_>>()}
+                </ul>
+            })}
+        </div>
+    }
+}
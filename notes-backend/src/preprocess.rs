@@ -0,0 +1,312 @@
+use std::f32::consts::PI;
+
+use realfft::RealFftPlanner;
+use serde::{Deserialize, Serialize};
+
+/// Cutoff of the high-pass filter if an upload doesn't pick one, below the lowest voices
+/// but above mains hum and its first harmonic.
+pub const HIGHPASS_HZ: f32 = 100.0;
+
+/// Pole of the dc blocker, puts its cutoff at about 13Hz.
+const DC_POLE: f64 = 0.995;
+/// 32ms frames for the noise gate, hopped by half a frame.
+const FFT: usize = 512;
+const HOP: usize = FFT / 2;
+/// A frequency has to be this many times louder than the noise to pass the gate, 6dB.
+const GATE: f32 = 2.0;
+/// Gain of gated frequencies, -20dB. Muting them completely sounds like underwater to whisper.
+const GATE_FLOOR: f32 = 0.1;
+/// How fast a closing gate fades out per hop, opening is instant so no word beginnings are lost.
+const GATE_RELEASE: f32 = 0.7;
+/// 400ms blocks for measuring the loudness like EBU R128 does.
+const BLOCK: usize = 6400;
+/// Blocks this much quieter than the average are pauses and don't count towards the loudness.
+const RELATIVE_GATE_DB: f32 = -10.0;
+const SILENCE_DB: f32 = -70.0;
+/// Loudness the speech is normalized to.
+const TARGET_DB: f32 = -20.0;
+/// Even a whisper from the back of the hall isn't amplified more than this.
+const MAX_GAIN_DB: f32 = 30.0;
+
+/// Steps run on a recording before transcribing it, all off by default.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PreprocessOptions {
+    /// Removes a constant offset some microphones add to the signal.
+    #[serde(default)]
+    pub dc: bool,
+    /// Cutoff in Hz of a high-pass filter against hum and rumble, `None` turns it off.
+    #[serde(default)]
+    pub highpass: Option<f32>,
+    /// Spectral noise gate against constant noise like projector fans.
+    #[serde(default)]
+    pub denoise: bool,
+    /// Brings the speech to the same loudness no matter how far away the microphone was.
+    #[serde(default)]
+    pub normalize: bool,
+}
+
+impl PreprocessOptions {
+    pub fn enabled(&self) -> bool {
+        self.dc || self.highpass.is_some() || self.denoise || self.normalize
+    }
+}
+
+/// Runs the enabled steps on `samples` (16KHz mono).
+/// The filters go first, so neither the noise profile nor the loudness is skewed by hum.
+pub fn process(samples: &mut [f32], options: &PreprocessOptions) {
+    if options.dc {
+        remove_dc(samples);
+    }
+    if let Some(cutoff) = options.highpass {
+        highpass(samples, cutoff);
+    }
+    if options.denoise {
+        denoise(samples);
+    }
+    if options.normalize {
+        normalize(samples);
+    }
+}
+
+fn remove_dc(samples: &mut [f32]) {
+    let (mut x1, mut y1) = (0.0f64, 0.0f64);
+    for sample in samples {
+        let x = *sample as f64;
+        y1 = x - x1 + DC_POLE * y1;
+        x1 = x;
+        *sample = y1 as f32;
+    }
+}
+
+/// Two butterworth biquads in a row, 24dB per octave below `cutoff`.
+fn highpass(samples: &mut [f32], cutoff: f32) {
+    let w0 = 2.0 * std::f64::consts::PI * cutoff as f64 / 16000.0;
+    let alpha = w0.sin() / (2.0 * std::f64::consts::FRAC_1_SQRT_2);
+    let cos = w0.cos();
+    let a0 = 1.0 + alpha;
+    let b = [
+        (1.0 + cos) / 2.0 / a0,
+        -(1.0 + cos) / a0,
+        (1.0 + cos) / 2.0 / a0,
+    ];
+    let a = [-2.0 * cos / a0, (1.0 - alpha) / a0];
+
+    for _ in 0..2 {
+        // transposed direct form II, f64 keeps the low cutoff stable
+        let (mut s1, mut s2) = (0.0f64, 0.0f64);
+        for sample in samples.iter_mut() {
+            let x = *sample as f64;
+            let y = b[0] * x + s1;
+            s1 = b[1] * x - a[0] * y + s2;
+            s2 = b[2] * x - a[1] * y;
+            *sample = y as f32;
+        }
+    }
+}
+
+/// Estimates the noise spectrum from the quietest tenth of the recording
+/// and turns down every frequency that isn't clearly louder than it.
+fn denoise(samples: &mut [f32]) {
+    if samples.len() < FFT {
+        return;
+    }
+    let mut planner = RealFftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(FFT);
+    let inverse = planner.plan_fft_inverse(FFT);
+    // sqrt of a hann window on both sides, overlapping by half they add up to exactly one
+    let window: Vec<f32> = (0..FFT)
+        .map(|i| (PI * i as f32 / FFT as f32).sin())
+        .collect();
+
+    // padding with a hop on both ends gives the first and last samples two frames as well
+    let frames = samples.len().div_ceil(HOP) + 1;
+    let mut padded = vec![0.0; (frames + 1) * HOP];
+    padded[HOP..HOP + samples.len()].copy_from_slice(samples);
+
+    let energy: Vec<f32> = (0..frames)
+        .map(|i| padded[i * HOP..i * HOP + FFT].iter().map(|x| x * x).sum())
+        .collect();
+    let mut sorted = energy.clone();
+    sorted.sort_by(f32::total_cmp);
+    let quiet = sorted[sorted.len() / 10];
+
+    let mut input = forward.make_input_vec();
+    let mut spectrum = forward.make_output_vec();
+    let mut noise = vec![0.0f32; spectrum.len()];
+    let mut count = 0;
+    for (i, _) in energy.iter().enumerate().filter(|(_, e)| **e <= quiet) {
+        for ((x, s), w) in input.iter_mut().zip(&padded[i * HOP..]).zip(&window) {
+            *x = s * w;
+        }
+        forward.process(&mut input, &mut spectrum).unwrap();
+        for (n, s) in noise.iter_mut().zip(&spectrum) {
+            *n += s.norm();
+        }
+        count += 1;
+    }
+    for n in &mut noise {
+        *n *= GATE / count as f32;
+    }
+
+    let mut out = vec![0.0; padded.len()];
+    let mut output = inverse.make_output_vec();
+    let mut gains = vec![1.0f32; spectrum.len()];
+    let mut open = vec![0.0f32; spectrum.len()];
+    for i in 0..frames {
+        for ((x, s), w) in input.iter_mut().zip(&padded[i * HOP..]).zip(&window) {
+            *x = s * w;
+        }
+        forward.process(&mut input, &mut spectrum).unwrap();
+        for (o, (s, n)) in open.iter_mut().zip(spectrum.iter().zip(&noise)) {
+            *o = if s.norm() > *n { 1.0 } else { GATE_FLOOR };
+        }
+        for (k, gain) in gains.iter_mut().enumerate() {
+            // averaging neighbouring frequencies avoids the chirping of single bins flickering
+            let neighbours = &open[k.saturating_sub(1)..(k + 2).min(open.len())];
+            let target = neighbours.iter().sum::<f32>() / neighbours.len() as f32;
+            *gain = target.max(*gain * GATE_RELEASE).max(GATE_FLOOR);
+        }
+        for (s, gain) in spectrum.iter_mut().zip(&gains) {
+            *s *= gain / FFT as f32;
+        }
+        inverse.process(&mut spectrum, &mut output).unwrap();
+        for ((o, x), w) in out[i * HOP..].iter_mut().zip(&output).zip(&window) {
+            *o += x * w;
+        }
+    }
+    samples.copy_from_slice(&out[HOP..HOP + samples.len()]);
+}
+
+/// Measures the loudness of the speech, ignoring pauses, and scales the recording to `TARGET_DB`.
+fn normalize(samples: &mut [f32]) {
+    let power: Vec<f32> = samples
+        .chunks(BLOCK)
+        .map(|block| block.iter().map(|x| x * x).sum::<f32>() / block.len() as f32)
+        .filter(|p| db(*p) > SILENCE_DB)
+        .collect();
+    if power.is_empty() {
+        return;
+    }
+    let mean = power.iter().sum::<f32>() / power.len() as f32;
+    let speech: Vec<f32> = power
+        .into_iter()
+        .filter(|p| db(*p) > db(mean) + RELATIVE_GATE_DB)
+        .collect();
+    let loudness = db(speech.iter().sum::<f32>() / speech.len() as f32);
+
+    let gain = 10f32.powf((TARGET_DB - loudness).min(MAX_GAIN_DB) / 20.0);
+    for sample in samples {
+        // the odd clap that ends up too loud is clipped rather than keeping all the speech quiet
+        *sample = (*sample * gain).clamp(-1.0, 1.0);
+    }
+}
+
+fn db(power: f32) -> f32 {
+    10.0 * power.max(1e-10).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(hz: f32, amplitude: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * PI * hz * i as f32 / 16000.0).sin() * amplitude)
+            .collect()
+    }
+
+    /// Deterministic white noise in [-amplitude, amplitude).
+    fn noise(amplitude: f32, len: usize) -> Vec<f32> {
+        let mut state = 1u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state as f32 / u32::MAX as f32 * 2.0 - 1.0) * amplitude
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|x| x * x).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0, |peak, x| peak.max(x.abs()))
+    }
+
+    #[test]
+    fn remove_dc_takes_out_the_offset() {
+        let mut samples: Vec<f32> = tone(440.0, 0.2, 32000).iter().map(|x| x + 0.3).collect();
+        remove_dc(&mut samples);
+        let tail = &samples[16000..];
+        let mean = tail.iter().sum::<f32>() / tail.len() as f32;
+        assert!(mean.abs() < 0.01, "mean {}", mean);
+        assert!((peak(tail) - 0.2).abs() < 0.02);
+    }
+
+    #[test]
+    fn highpass_rejects_dc_and_hum() {
+        let mut dc = vec![0.5; 16000];
+        highpass(&mut dc, HIGHPASS_HZ);
+        assert!(peak(&dc[8000..]) < 1e-4);
+
+        let mut hum = tone(30.0, 0.5, 32000);
+        highpass(&mut hum, HIGHPASS_HZ);
+        // 24dB per octave, 30Hz is well over an octave below the cutoff
+        assert!(rms(&hum[16000..]) < rms(&tone(30.0, 0.5, 16000)) / 30.0);
+    }
+
+    #[test]
+    fn highpass_keeps_speech() {
+        let mut speech = tone(1000.0, 0.5, 16000);
+        highpass(&mut speech, HIGHPASS_HZ);
+        assert!((rms(&speech[8000..]) / rms(&tone(1000.0, 0.5, 8000)) - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn normalize_brings_speech_to_the_target_level() {
+        // a sine at -20dB loudness peaks at sqrt(2) * 0.1
+        let mut quiet = tone(440.0, 0.01, 32000);
+        normalize(&mut quiet);
+        assert!((peak(&quiet) - 0.1414).abs() < 0.005, "peak {}", peak(&quiet));
+
+        let mut loud = tone(440.0, 0.9, 32000);
+        normalize(&mut loud);
+        assert!((peak(&loud) - 0.1414).abs() < 0.005, "peak {}", peak(&loud));
+    }
+
+    #[test]
+    fn normalize_caps_the_gain_and_clips() {
+        // 33dB too quiet, only raised by 30dB
+        let mut faint = tone(440.0, 0.003, 32000);
+        normalize(&mut faint);
+        let expected = 0.003 * 10f32.powf(MAX_GAIN_DB / 20.0);
+        assert!((peak(&faint) - expected).abs() < 0.002, "peak {}", peak(&faint));
+
+        let mut clap = tone(440.0, 0.01, 32000);
+        clap[20000] = 0.5;
+        normalize(&mut clap);
+        assert_eq!(peak(&clap), 1.0);
+    }
+
+    #[test]
+    fn normalize_leaves_silence_alone() {
+        let mut silence = vec![0.0; 16000];
+        normalize(&mut silence);
+        assert!(silence.iter().all(|x| *x == 0.0));
+    }
+
+    #[test]
+    fn denoise_turns_down_the_noise_but_not_the_speech() {
+        let mut samples = noise(0.02, 48000);
+        let speech = tone(1000.0, 0.3, 16000);
+        for (s, t) in samples[32000..].iter_mut().zip(&speech) {
+            *s += t;
+        }
+        let noise_before = rms(&samples[4000..28000]);
+        let speech_before = rms(&samples[36000..44000]);
+        denoise(&mut samples);
+        assert!(rms(&samples[4000..28000]) < noise_before / 3.0);
+        assert!((rms(&samples[36000..44000]) / speech_before - 1.0).abs() < 0.1);
+    }
+}
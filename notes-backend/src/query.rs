@@ -1,12 +1,25 @@
 use leptos::prelude::*;
 
 #[cfg(feature = "ssr")]
-use sqlx::query_as;
+use sqlx::{query_as, query_scalar, FromRow, Pool, Sqlite};
+#[cfg(feature = "ssr")]
+use std::collections::HashMap;
+
+use crate::datatypes::{Note, Summary};
 
-use crate::datatypes::Note;
+#[cfg(feature = "ssr")]
+use crate::{
+    datatypes::{AppState, Segment, StructuredSummary},
+    markdown::render,
+};
 
 #[cfg(feature = "ssr")]
-use crate::datatypes::AppState;
+#[derive(FromRow)]
+struct NoteSegment {
+    entry_id: u32,
+    #[sqlx(flatten)]
+    segment: Segment,
+}
 
 #[server]
 pub async fn query() -> Result<Vec<Note>, ServerFnError> {
@@ -14,14 +27,32 @@ pub async fn query() -> Result<Vec<Note>, ServerFnError> {
     {
         let state = expect_context::<AppState>();
 
-        let out = query_as(
+        let mut out: Vec<Note> = query_as(
+            r#"
+        SELECT entries.id, name, transcript, summary, summary_model, draft, title, language, translation, removed, jobs.state, jobs.error, jobs.progress, jobs.eta FROM entries
+        LEFT JOIN jobs ON jobs.id = (SELECT MAX(id) FROM jobs WHERE entry_id = entries.id)
+    "#,
+        )
+        .fetch_all(&state.pool)
+        .await
+        .unwrap();
+
+        let rows: Vec<NoteSegment> = query_as(
             r#"
-        SELECT name, transcript, summary FROM entries
+        SELECT entry_id, "index", start_ms, end_ms, text, probability, speaker FROM segments
+        ORDER BY entry_id, "index"
     "#,
         )
         .fetch_all(&state.pool)
         .await
         .unwrap();
+        let mut segments: HashMap<u32, Vec<Segment>> = HashMap::new();
+        for row in rows {
+            segments.entry(row.entry_id).or_default().push(row.segment);
+        }
+        for note in &mut out {
+            note.segments = segments.remove(&note.id).unwrap_or_default();
+        }
 
         Ok(out)
     }
@@ -30,3 +61,102 @@ pub async fn query() -> Result<Vec<Note>, ServerFnError> {
         Err(ServerFnError::ServerError("Not on server".to_string()))
     }
 }
+
+/// The summary of a note as html, rendered on the server so the page shows it without javascript.
+#[server]
+pub async fn note_summary(id: u32) -> Result<Summary, ServerFnError> {
+    #[cfg(feature = "ssr")]
+    {
+        let state = expect_context::<AppState>();
+
+        let Some((name, summary, model)): Option<(String, Option<String>, Option<String>)> =
+            query_as(
+                r#"
+        SELECT name, summary, summary_model FROM entries
+        WHERE id = ?
+    "#,
+            )
+            .bind(id)
+            .fetch_optional(&state.pool)
+            .await
+            .unwrap()
+        else {
+            return Err(ServerFnError::ServerError("Unknown note".to_string()));
+        };
+
+        Ok(Summary {
+            name,
+            html: summary.as_deref().map(render),
+            model,
+            structured: structured_summary(&state.pool, id).await.unwrap(),
+        })
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        Err(ServerFnError::ServerError("Not on server".to_string()))
+    }
+}
+
+/// The summary of a note from the tables of its parts, `None` if it has no structured summary.
+#[cfg(feature = "ssr")]
+pub async fn structured_summary(
+    pool: &Pool<Sqlite>,
+    id: u32,
+) -> Result<Option<StructuredSummary>, sqlx::Error> {
+    let Some(title): Option<String> = query_scalar(
+        r#"
+        SELECT title FROM entries
+        WHERE id = ?
+    "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+    .flatten() else {
+        return Ok(None);
+    };
+
+    Ok(Some(StructuredSummary {
+        title,
+        sections: query_as(
+            r#"
+        SELECT heading, points FROM sections
+        WHERE entry_id = ?
+        ORDER BY "index"
+    "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?,
+        key_terms: query_as(
+            r#"
+        SELECT term, definition FROM key_terms
+        WHERE entry_id = ?
+        ORDER BY "index"
+    "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?,
+        formulas: query_as(
+            r#"
+        SELECT formula, description FROM formulas
+        WHERE entry_id = ?
+        ORDER BY "index"
+    "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?,
+        open_questions: query_scalar(
+            r#"
+        SELECT question FROM open_questions
+        WHERE entry_id = ?
+        ORDER BY "index"
+    "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?,
+    }))
+}
@@ -0,0 +1,191 @@
+use leptos::prelude::*;
+
+#[cfg(feature = "ssr")]
+use sqlx::{query, query_as, query_scalar, types::Json};
+#[cfg(feature = "ssr")]
+use whisper_rs::{get_lang_id, get_lang_str};
+
+use crate::datatypes::{RerunStep, Version};
+
+#[cfg(feature = "ssr")]
+use crate::{
+    datatypes::AppState,
+    jobs::{JobOptions, JobQueue},
+    models::MODELS,
+    transcription::vocabulary_prompt,
+};
+
+/// Redoes the transcript, the summary or both of a note through the job queue,
+/// keeping its current output, if it has any, as a version. Optional arguments that are missing or
+/// empty keep the options of the last run:
+/// - `language`: whisper language code or name or `auto`
+/// - `model`: one of `models::MODELS`, `default` for the configured one
+/// - `vocabulary`: terms separated by commas or new lines, replaces the course vocabulary as well
+/// - `summary_model`: Ollama model writing the summary, `default` for the configured one
+#[server]
+pub async fn rerun(
+    id: u32,
+    step: RerunStep,
+    language: Option<String>,
+    model: Option<String>,
+    vocabulary: Option<String>,
+    summary_model: Option<String>,
+) -> Result<(), ServerFnError> {
+    #[cfg(feature = "ssr")]
+    {
+        let state = expect_context::<AppState>();
+
+        let Some(transcript): Option<Option<String>> =
+            query_scalar("SELECT transcript FROM entries WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&state.pool)
+                .await?
+        else {
+            return Err(ServerFnError::ServerError("Unknown note".to_string()));
+        };
+        if transcript.is_none() && step == RerunStep::Summary {
+            return Err(ServerFnError::ServerError(
+                "The note has no transcript to summarize".to_string(),
+            ));
+        }
+
+        let last: Option<Json<JobOptions>> = query_scalar(
+            r#"
+        SELECT options FROM jobs
+        WHERE entry_id = ?
+        ORDER BY id DESC
+        LIMIT 1
+    "#,
+        )
+        .bind(id)
+        .fetch_optional(&state.pool)
+        .await?
+        .flatten();
+        let mut options = last.map(|o| o.0).unwrap_or_default();
+        options.rerun = Some(step);
+        match language.as_deref() {
+            None | Some("") => {}
+            Some("auto") => options.transcription.language = None,
+            Some(language) => match get_lang_id(language).and_then(get_lang_str) {
+                Some(language) => options.transcription.language = Some(language.to_string()),
+                None => return Err(ServerFnError::ServerError("Unknown language".to_string())),
+            },
+        }
+        match model.as_deref() {
+            None | Some("") => {}
+            Some("default") => options.transcription.model = None,
+            Some(model) if MODELS.contains(&model) => {
+                options.transcription.model = Some(model.to_string())
+            }
+            Some(_) => return Err(ServerFnError::ServerError("Unknown model".to_string())),
+        }
+        if let Some(prompt) = vocabulary.as_deref().and_then(vocabulary_prompt) {
+            options.transcription.prompt = Some(prompt);
+        }
+        match summary_model.as_deref() {
+            None | Some("") => {}
+            Some("default") => options.summary.model = None,
+            Some(model) => options.summary.model = Some(model.to_string()),
+        }
+
+        // checking for an unfinished job and both inserts happen at once,
+        // so a double click can't queue the note twice
+        let mut tx = state.pool.begin().await?;
+        if !JobQueue::enqueue_idle(&mut tx, id, options).await? {
+            return Err(ServerFnError::ServerError(
+                "The note is still being processed".to_string(),
+            ));
+        }
+        query(
+            r#"
+        INSERT INTO versions (entry_id, transcript, summary, summary_model, structured, language, translation, segments)
+        SELECT id, transcript, summary, summary_model, CASE WHEN title IS NULL THEN NULL ELSE json_object(
+            'title', title,
+            'sections', json((
+                SELECT json_group_array(json_object('heading', heading, 'points', json(points)))
+                FROM (SELECT * FROM sections WHERE entry_id = entries.id ORDER BY "index")
+            )),
+            'key_terms', json((
+                SELECT json_group_array(json_object('term', term, 'definition', definition))
+                FROM (SELECT * FROM key_terms WHERE entry_id = entries.id ORDER BY "index")
+            )),
+            'formulas', json((
+                SELECT json_group_array(json_object('formula', formula, 'description', description))
+                FROM (SELECT * FROM formulas WHERE entry_id = entries.id ORDER BY "index")
+            )),
+            'open_questions', json((
+                SELECT json_group_array(question)
+                FROM (SELECT * FROM open_questions WHERE entry_id = entries.id ORDER BY "index")
+            ))
+        ) END, language, translation, (
+            SELECT json_group_array(json_object(
+                'index', "index",
+                'start_ms', start_ms,
+                'end_ms', end_ms,
+                'text', text,
+                'probability', probability,
+                'speaker', speaker
+            )) FROM (SELECT * FROM segments WHERE entry_id = entries.id ORDER BY "index")
+        ) FROM entries
+        WHERE id = ? AND (transcript IS NOT NULL OR summary IS NOT NULL)
+    "#,
+        )
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        state.jobs.queued(id);
+
+        Ok(())
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        Err(ServerFnError::ServerError("Not on server".to_string()))
+    }
+}
+
+/// Tries the failed job of a note again from its first attempt, with the same options.
+/// Unlike `rerun` nothing is redone, so no version is kept.
+#[server]
+pub async fn retry(id: u32) -> Result<(), ServerFnError> {
+    #[cfg(feature = "ssr")]
+    {
+        let state = expect_context::<AppState>();
+
+        if !state.jobs.retry(id).await? {
+            return Err(ServerFnError::ServerError("The note hasn't failed".to_string()));
+        }
+
+        Ok(())
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        Err(ServerFnError::ServerError("Not on server".to_string()))
+    }
+}
+
+/// The earlier outputs of a note, newest first.
+#[server]
+pub async fn versions(id: u32) -> Result<Vec<Version>, ServerFnError> {
+    #[cfg(feature = "ssr")]
+    {
+        let state = expect_context::<AppState>();
+
+        let out = query_as(
+            r#"
+        SELECT id, created_at, transcript, summary, summary_model, structured, language, translation, segments FROM versions
+        WHERE entry_id = ?
+        ORDER BY id DESC
+    "#,
+        )
+        .bind(id)
+        .fetch_all(&state.pool)
+        .await?;
+
+        Ok(out)
+    }
+    #[cfg(not(feature = "ssr"))]
+    {
+        Err(ServerFnError::ServerError("Not on server".to_string()))
+    }
+}
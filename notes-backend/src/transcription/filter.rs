@@ -0,0 +1,224 @@
+use crate::datatypes::{RemovalReason, Removed, Segment};
+
+/// Segments whisper was less sure about than this are mostly made up.
+const MIN_PROBABILITY: f32 = 0.2;
+/// Loops are searched for in groups of up to this many words or segments.
+const MAX_WORDS: usize = 8;
+const MAX_SEGMENTS: usize = 4;
+/// A single word may well be said three times, "very, very, very".
+const WORD_REPEATS: usize = 4;
+/// Longer groups of words and segments repeating this often are a loop.
+const REPEATS: usize = 3;
+/// A segment is one of the phrases if it has at most this many words besides it, "thanks for watching, bye".
+/// A longer sentence merely containing a phrase is what was said.
+const PHRASE_EXTRA_WORDS: usize = 2;
+
+/// Phrases whisper learned from the subtitles of online videos and puts into silence and music.
+/// Compared in lowercase without punctuation.
+const PHRASES: &[&str] = &[
+    "thanks for watching",
+    "thank you for watching",
+    "thank you so much for watching",
+    "please subscribe",
+    "like and subscribe",
+    "subscribe to my channel",
+    "dont forget to subscribe",
+    "see you in the next video",
+    "amaraorg",
+    "untertitel im auftrag des zdf",
+    "untertitelung des zdf",
+    "vielen dank fürs zuschauen",
+    "merci davoir regardé",
+    "gracias por ver el video",
+    "sottotitoli creati dalla comunità",
+];
+
+/// Drops unlikely segments, known phrases and loops of segments, and cuts loops of words
+/// out of the remaining segments. Everything taken out is returned, ordered by time.
+pub fn filter(segments: Vec<Segment>) -> (Vec<Segment>, Vec<Removed>) {
+    let mut removed = Vec::new();
+    let mut likely = Vec::new();
+    for segment in segments {
+        let text = normalize(&segment.text);
+        let reason = if text.is_empty()
+            || annotation(&segment.text)
+            || phrase(&text)
+        {
+            Some(RemovalReason::Phrase)
+        } else if segment.probability < MIN_PROBABILITY {
+            Some(RemovalReason::Probability)
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => removed.push(removal(&segment, segment.text.clone(), reason)),
+            None => likely.push(segment),
+        }
+    }
+
+    let keys: Vec<String> = likely.iter().map(|s| normalize(&s.text)).collect();
+    let keep = loops(&keys, MAX_SEGMENTS, |_| REPEATS);
+    let mut out = Vec::new();
+    for (mut segment, keep) in likely.into_iter().zip(keep) {
+        if !keep {
+            removed.push(removal(
+                &segment,
+                segment.text.clone(),
+                RemovalReason::Repetition,
+            ));
+            continue;
+        }
+
+        let words: Vec<&str> = segment.text.split_whitespace().collect();
+        let keys: Vec<String> = words.iter().map(|w| normalize(w)).collect();
+        let keep = loops(&keys, MAX_WORDS, |n| {
+            if n == 1 {
+                WORD_REPEATS
+            } else {
+                REPEATS
+            }
+        });
+        if keep.contains(&false) {
+            let (kept, cut): (Vec<_>, Vec<_>) = words.iter().zip(keep).partition(|(_, k)| *k);
+            let cut = cut
+                .into_iter()
+                .map(|(w, _)| *w)
+                .collect::<Vec<_>>()
+                .join(" ");
+            removed.push(removal(&segment, cut, RemovalReason::Repetition));
+            segment.text = kept
+                .into_iter()
+                .map(|(w, _)| *w)
+                .collect::<Vec<_>>()
+                .join(" ");
+        }
+        out.push(segment);
+    }
+
+    removed.sort_by_key(|r| r.start_ms);
+    (out, removed)
+}
+
+fn removal(segment: &Segment, text: String, reason: RemovalReason) -> Removed {
+    Removed {
+        start_ms: segment.start_ms,
+        end_ms: segment.end_ms,
+        text,
+        reason,
+        translation: false,
+    }
+}
+
+/// Whether a normalized segment is one of `PHRASES`, give or take a word or two.
+fn phrase(text: &str) -> bool {
+    let words = text.split(' ').count();
+    PHRASES
+        .iter()
+        .any(|p| text.contains(p) && words <= p.split(' ').count() + PHRASE_EXTRA_WORDS)
+}
+
+/// Lowercase words without punctuation, so `Thanks for watching!` matches `thanks for watching`.
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// `[Music]`, `(applause)` and the like describe sounds, they aren't part of the lecture.
+fn annotation(text: &str) -> bool {
+    let text = text.trim();
+    (text.starts_with('[') && text.ends_with(']')) || (text.starts_with('(') && text.ends_with(')'))
+}
+
+/// Marks which of `keys` to keep so that a group of up to `max` keys that follows itself
+/// at least `repeats(group size)` times only stays once.
+fn loops(keys: &[String], max: usize, repeats: impl Fn(usize) -> usize) -> Vec<bool> {
+    let mut keep = vec![true; keys.len()];
+    let mut i = 0;
+    'next: while i < keys.len() {
+        for n in 1..=max.min(keys.len() - i) {
+            let group = &keys[i..i + n];
+            let mut count = 1;
+            while keys
+                .get(i + count * n..)
+                .is_some_and(|rest| rest.starts_with(group))
+            {
+                count += 1;
+            }
+            if count >= repeats(n) {
+                keep[i + n..i + count * n].fill(false);
+                i += count * n;
+                continue 'next;
+            }
+        }
+        i += 1;
+    }
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(text: &str) -> Vec<String> {
+        text.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn segment(text: &str) -> Segment {
+        Segment {
+            index: 0,
+            start_ms: 0,
+            end_ms: 1000,
+            text: text.to_string(),
+            probability: 1.0,
+            speaker: None,
+        }
+    }
+
+    #[test]
+    fn drops_segments_that_are_a_phrase() {
+        let (kept, removed) = filter(vec![
+            segment("Thanks for watching!"),
+            segment("Thank you for watching, bye bye."),
+        ]);
+        assert!(kept.is_empty());
+        assert_eq!(removed.len(), 2);
+        assert!(removed.iter().all(|r| r.reason == RemovalReason::Phrase));
+    }
+
+    #[test]
+    fn keeps_sentences_containing_a_phrase() {
+        let text = "Some of you asked why the video ends with thanks for watching, that is the outro.";
+        let (kept, removed) = filter(vec![segment(text)]);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].text, text);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn loops_keep_a_repeated_group_once() {
+        let keep = loops(&keys("a b a b a b c"), 4, |_| 3);
+        assert_eq!(keep, [true, true, false, false, false, false, true]);
+    }
+
+    #[test]
+    fn loops_need_enough_repeats() {
+        let keep = loops(&keys("very very very good"), 8, |n| if n == 1 { 4 } else { 3 });
+        assert!(keep.iter().all(|k| *k));
+        let keep = loops(&keys("very very very very good"), 8, |n| if n == 1 { 4 } else { 3 });
+        assert_eq!(keep, [true, false, false, false, true]);
+    }
+
+    #[test]
+    fn loops_ignore_groups_longer_than_max() {
+        let keep = loops(&keys("a b c a b c a b c"), 2, |_| 3);
+        assert!(keep.iter().all(|k| *k));
+    }
+}
@@ -0,0 +1,70 @@
+use async_trait::async_trait;
+
+use super::{format_segments, Transcriber, Transcript, TranscriptionError, TranscriptionOptions};
+use crate::datatypes::Segment;
+
+/// Returns the same transcript for every recording without looking at it,
+/// so uploads, jobs and summaries can be tried without a model.
+pub struct MockTranscriber;
+
+const SEGMENTS: &[(u32, u32, &str)] = &[
+    (0, 4200, "Good morning, today we start with eigenvalues."),
+    (
+        4200,
+        9800,
+        "A vector whose direction doesn't change under a matrix is an eigenvector.",
+    ),
+    (9800, 15000, "Next week we will look at diagonalization."),
+];
+
+fn segments() -> Vec<Segment> {
+    SEGMENTS
+        .iter()
+        .enumerate()
+        .map(|(index, (start_ms, end_ms, text))| Segment {
+            index: index as u32,
+            start_ms: *start_ms,
+            end_ms: *end_ms,
+            text: text.to_string(),
+            probability: 1.0,
+            speaker: None,
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Transcriber for MockTranscriber {
+    async fn transcribe(
+        &self,
+        _file: String,
+        options: TranscriptionOptions,
+        mut progress: Box<dyn FnMut(i32) + Send>,
+    ) -> Result<Transcript, TranscriptionError> {
+        progress(100);
+        let mut segments = segments();
+        if options.diarize {
+            for segment in &mut segments {
+                segment.speaker = Some(0);
+            }
+        }
+        let language = options.language.unwrap_or_else(|| "en".to_string());
+        // the canned text is english already, it stands in for the translation as well
+        let translation = (options.translate && language != "en").then(|| segments.clone());
+        Ok(Transcript {
+            text: format_segments(&segments),
+            segments,
+            language,
+            translation,
+            removed: Vec::new(),
+        })
+    }
+
+    async fn transcribe_samples(
+        &self,
+        _samples: Vec<f32>,
+        options: TranscriptionOptions,
+    ) -> Result<Transcript, TranscriptionError> {
+        self.transcribe(String::new(), options, Box::new(|_| {}))
+            .await
+    }
+}
@@ -0,0 +1,193 @@
+use std::{fmt, mem, sync::Arc};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+use whisper_rs::WhisperError;
+
+use crate::{
+    audio::AudioError,
+    datatypes::{Removed, Segment},
+    diarize,
+    models::ModelError,
+    preprocess::PreprocessOptions,
+};
+
+mod filter;
+mod mock;
+mod openai;
+mod whisper;
+
+pub use mock::MockTranscriber;
+pub use openai::OpenAiTranscriber;
+pub use whisper::WhisperTranscriber;
+
+/// Turns a recording into a transcript. `TRANSCRIBER` picks the implementation,
+/// the mock lets the pipeline run without a model.
+#[async_trait]
+pub trait Transcriber: Send + Sync {
+    /// `progress` is called with the progress in percent, possibly from another thread.
+    async fn transcribe(
+        &self,
+        file: String,
+        options: TranscriptionOptions,
+        progress: Box<dyn FnMut(i32) + Send>,
+    ) -> Result<Transcript, TranscriptionError>;
+
+    /// Transcribes 16KHz mono samples, for the live captions of a recording in progress.
+    async fn transcribe_samples(
+        &self,
+        samples: Vec<f32>,
+        options: TranscriptionOptions,
+    ) -> Result<Transcript, TranscriptionError>;
+}
+
+#[derive(Debug)]
+pub enum TranscriptionError {
+    Audio(AudioError),
+    Whisper(WhisperError),
+    Model(ModelError),
+    /// the transcription server couldn't be reached or answered with an error
+    Http(reqwest::Error),
+    Stopped,
+}
+
+impl fmt::Display for TranscriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscriptionError::Audio(e) => e.fmt(f),
+            TranscriptionError::Whisper(e) => write!(f, "whisper failed: {}", e),
+            TranscriptionError::Model(e) => e.fmt(f),
+            TranscriptionError::Http(e) => write!(f, "transcription server failed: {}", e),
+            TranscriptionError::Stopped => write!(f, "transcription service stopped"),
+        }
+    }
+}
+
+impl std::error::Error for TranscriptionError {}
+
+impl From<AudioError> for TranscriptionError {
+    fn from(e: AudioError) -> Self {
+        TranscriptionError::Audio(e)
+    }
+}
+
+impl From<ModelError> for TranscriptionError {
+    fn from(e: ModelError) -> Self {
+        TranscriptionError::Model(e)
+    }
+}
+
+impl From<reqwest::Error> for TranscriptionError {
+    fn from(e: reqwest::Error) -> Self {
+        TranscriptionError::Http(e)
+    }
+}
+
+impl From<WhisperError> for TranscriptionError {
+    fn from(e: WhisperError) -> Self {
+        TranscriptionError::Whisper(e)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TranscriptionOptions {
+    /// Whisper language code of the recording, `None` lets whisper detect it.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Also translate the recording into english.
+    #[serde(default)]
+    pub translate: bool,
+    /// Vocabulary of the course and the upload, given to whisper as its initial prompt.
+    #[serde(default)]
+    pub prompt: Option<String>,
+    /// One of `models::MODELS`, `None` uses the model of the server config.
+    /// Only the whisper transcriber can switch models.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Cleans up the audio before it's transcribed.
+    #[serde(default)]
+    pub preprocess: PreprocessOptions,
+    /// Labels the segments with who is speaking.
+    #[serde(default)]
+    pub diarize: bool,
+}
+
+pub struct Transcript {
+    pub text: String,
+    pub segments: Vec<Segment>,
+    /// Whisper language code, detected by whisper unless it was given in the options.
+    pub language: String,
+    /// English translation, if one was requested and the recording isn't english already.
+    pub translation: Option<Vec<Segment>>,
+    /// What `filter` took out.
+    pub removed: Vec<Removed>,
+}
+
+impl Transcript {
+    /// Takes whisper's hallucinations out of the transcript and its translation.
+    pub fn filter(&mut self) {
+        let (segments, mut removed) = filter::filter(mem::take(&mut self.segments));
+        self.segments = reindex(segments);
+        self.text = format_segments(&self.segments);
+        if let Some(translation) = self.translation.take() {
+            let (translation, translation_removed) = filter::filter(translation);
+            removed.extend(translation_removed.into_iter().map(|r| Removed {
+                translation: true,
+                ..r
+            }));
+            self.translation = Some(reindex(translation));
+        }
+        self.removed = removed;
+    }
+}
+
+fn reindex(mut segments: Vec<Segment>) -> Vec<Segment> {
+    for (index, segment) in segments.iter_mut().enumerate() {
+        segment.index = index as u32;
+    }
+    segments
+}
+
+/// The plain text transcript that is stored on the note and summarized.
+pub fn format_segments(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .map(|s| match s.speaker_label() {
+            Some(speaker) => format!(
+                "[{} - {}] {}: {}\n",
+                s.start_ms / 10,
+                s.end_ms / 10,
+                speaker,
+                s.text
+            ),
+            None => format!("[{} - {}]: {}\n", s.start_ms / 10, s.end_ms / 10, s.text),
+        })
+        .collect()
+}
+
+/// Turns terms separated by commas or new lines into the initial prompt for whisper,
+/// `None` if there are none.
+pub fn vocabulary_prompt(vocabulary: &str) -> Option<String> {
+    let terms: Vec<&str> = vocabulary
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .collect();
+    (!terms.is_empty()).then(|| format!("{}.", terms.join(", ")))
+}
+
+/// Clusters the segments by speaker, off the async threads. `samples` are the 16KHz mono
+/// samples the segment timestamps point into.
+async fn label_speakers(
+    samples: Arc<Vec<f32>>,
+    mut segments: Vec<Segment>,
+    threshold: f32,
+) -> Result<Vec<Segment>, TranscriptionError> {
+    spawn_blocking(move || {
+        diarize::diarize(&samples, &mut segments, threshold);
+        segments
+    })
+    .await
+    .map_err(|_| TranscriptionError::Stopped)
+}
@@ -0,0 +1,228 @@
+use std::{mem, sync::Arc};
+
+use async_trait::async_trait;
+use reqwest::{
+    multipart::{Form, Part},
+    Client,
+};
+use serde::Deserialize;
+use tokio::task::spawn_blocking;
+use whisper_rs::{get_lang_id, get_lang_str};
+
+use super::{
+    format_segments, label_speakers, Transcriber, Transcript, TranscriptionError,
+    TranscriptionOptions,
+};
+use crate::{
+    audio::{decode, encode_wav, AudioError},
+    config::Config,
+    datatypes::Segment,
+    diarize, preprocess,
+};
+
+/// Sends recordings to a server implementing OpenAI's `/v1/audio/transcriptions`,
+/// e.g. OpenAI itself or a self hosted faster-whisper server.
+pub struct OpenAiTranscriber {
+    client: Client,
+    url: String,
+    api_key: Option<String>,
+    model: String,
+    diarization_threshold: f32,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    /// full name of the language, e.g. `english`
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    segments: Vec<ResponseSegment>,
+}
+
+#[derive(Deserialize)]
+struct ResponseSegment {
+    /// seconds
+    start: f64,
+    end: f64,
+    text: String,
+    #[serde(default)]
+    avg_logprob: f32,
+}
+
+impl OpenAiTranscriber {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            client: Client::new(),
+            url: config.transcription_url.trim_end_matches('/').to_string(),
+            api_key: config.transcription_api_key.clone(),
+            model: config.transcription_model.clone(),
+            diarization_threshold: config.diarization_threshold,
+        }
+    }
+
+    /// `endpoint` is `transcriptions` or `translations`, both answer with the same format.
+    async fn request(
+        &self,
+        endpoint: &str,
+        file: &str,
+        bytes: Vec<u8>,
+        options: &TranscriptionOptions,
+    ) -> Result<Response, TranscriptionError> {
+        let mut form = Form::new()
+            .part("file", Part::bytes(bytes).file_name(file.to_string()))
+            .text("model", self.model.clone())
+            .text("response_format", "verbose_json")
+            .text("timestamp_granularities[]", "segment");
+        if let Some(prompt) = &options.prompt {
+            form = form.text("prompt", prompt.clone());
+        }
+        // translations are always english, the language only describes the input of a transcription
+        if let (Some(language), "transcriptions") = (&options.language, endpoint) {
+            form = form.text("language", language.clone());
+        }
+
+        let mut req = self
+            .client
+            .post(format!("{}/v1/audio/{}", self.url, endpoint))
+            .multipart(form);
+        if let Some(api_key) = &self.api_key {
+            req = req.bearer_auth(api_key);
+        }
+        Ok(req.send().await?.error_for_status()?.json().await?)
+    }
+}
+
+fn to_segments(response: Response) -> Vec<Segment> {
+    response
+        .segments
+        .into_iter()
+        .enumerate()
+        .map(|(index, s)| Segment {
+            index: index as u32,
+            start_ms: (s.start * 1000.0) as u32,
+            end_ms: (s.end * 1000.0) as u32,
+            text: s.text.trim().to_string(),
+            probability: s.avg_logprob.exp(),
+            speaker: None,
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Transcriber for OpenAiTranscriber {
+    async fn transcribe(
+        &self,
+        file: String,
+        options: TranscriptionOptions,
+        progress: Box<dyn FnMut(i32) + Send>,
+    ) -> Result<Transcript, TranscriptionError> {
+        let diarize = options.diarize;
+        let (name, bytes, samples) = if options.preprocess.enabled() {
+            // the server only gets to hear the cleaned up audio
+            let preprocessing = options.preprocess.clone();
+            let file = file.clone();
+            let (bytes, samples) = spawn_blocking(move || -> Result<_, AudioError> {
+                let mut samples = decode(&file)?;
+                preprocess::process(&mut samples, &preprocessing);
+                Ok((encode_wav(&samples)?, samples))
+            })
+            .await
+            .map_err(|_| TranscriptionError::Stopped)??;
+            ("recording.wav".to_string(), bytes, Some(samples))
+        } else {
+            let bytes = tokio::fs::read(&file).await.map_err(AudioError::from)?;
+            (file.clone(), bytes, None)
+        };
+        let transcript = self.send(&name, bytes, options, progress).await?;
+        if !diarize {
+            return Ok(transcript);
+        }
+
+        let samples = match samples {
+            Some(samples) => samples,
+            None => spawn_blocking(move || decode(&file))
+                .await
+                .map_err(|_| TranscriptionError::Stopped)??,
+        };
+        self.label_speakers(samples, transcript).await
+    }
+
+    async fn transcribe_samples(
+        &self,
+        mut samples: Vec<f32>,
+        options: TranscriptionOptions,
+    ) -> Result<Transcript, TranscriptionError> {
+        preprocess::process(&mut samples, &options.preprocess);
+        let bytes = encode_wav(&samples)?;
+        let diarize = options.diarize;
+        let transcript = self
+            .send("live.wav", bytes, options, Box::new(|_| {}))
+            .await?;
+        if !diarize {
+            return Ok(transcript);
+        }
+        self.label_speakers(samples, transcript).await
+    }
+}
+
+impl OpenAiTranscriber {
+    /// The server knows nothing about speakers, they are found in the audio here.
+    async fn label_speakers(
+        &self,
+        samples: Vec<f32>,
+        mut transcript: Transcript,
+    ) -> Result<Transcript, TranscriptionError> {
+        transcript.segments = label_speakers(
+            Arc::new(samples),
+            mem::take(&mut transcript.segments),
+            self.diarization_threshold,
+        )
+        .await?;
+        if let Some(translation) = &mut transcript.translation {
+            diarize::assign(&transcript.segments, translation);
+        }
+        transcript.text = format_segments(&transcript.segments);
+        Ok(transcript)
+    }
+
+    async fn send(
+        &self,
+        file: &str,
+        bytes: Vec<u8>,
+        options: TranscriptionOptions,
+        mut progress: Box<dyn FnMut(i32) + Send>,
+    ) -> Result<Transcript, TranscriptionError> {
+        let response = self
+            .request("transcriptions", file, bytes.clone(), &options)
+            .await?;
+        // whisper.cpp knows the full names as well, so they map back to the usual codes
+        let language = match &options.language {
+            Some(language) => language.clone(),
+            None => response
+                .language
+                .as_deref()
+                .and_then(get_lang_id)
+                .and_then(get_lang_str)
+                .unwrap_or("en")
+                .to_string(),
+        };
+        let segments = to_segments(response);
+
+        let translation = if options.translate && language != "en" {
+            progress(50);
+            let response = self.request("translations", file, bytes, &options).await?;
+            Some(to_segments(response))
+        } else {
+            None
+        };
+        progress(100);
+
+        Ok(Transcript {
+            text: format_segments(&segments),
+            segments,
+            language,
+            translation,
+            removed: Vec::new(),
+        })
+    }
+}
@@ -0,0 +1,581 @@
+use std::{
+    collections::HashMap,
+    mem,
+    ops::Range,
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use futures_util::future::try_join_all;
+use tokio::{
+    runtime::Handle,
+    select, spawn,
+    sync::{mpsc, oneshot, OnceCell},
+    task::spawn_blocking,
+    time::sleep,
+};
+use whisper_rs::{
+    get_lang_str, FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters,
+    WhisperError, WhisperState, WhisperTokenId,
+};
+
+use super::{
+    format_segments, label_speakers, Transcriber, Transcript, TranscriptionError,
+    TranscriptionOptions,
+};
+use crate::{
+    audio::{decode, AudioError},
+    config::Config,
+    datatypes::Segment,
+    diarize, models, preprocess,
+    vad::{self, Speech},
+};
+
+/// Recordings are split into chunks of this length that are transcribed independently.
+const CHUNK_MS: usize = 5 * 60 * 1000;
+/// Neighbouring chunks share this much audio, so a sentence cut in one chunk is whole in the other.
+const OVERLAP_MS: usize = 10 * 1000;
+/// Models other than the configured one are unloaded after going unused for this long.
+const IDLE: Duration = Duration::from_secs(10 * 60);
+
+/// Work for one of the whisper states, run on its worker thread.
+type Task = Box<dyn FnOnce(&mut WhisperState) + Send>;
+/// The workers of every model by name, filled in once the model is loaded.
+type Models = HashMap<String, Arc<OnceCell<Arc<Workers>>>>;
+
+/// Loads every model once and shares it between a fixed number of worker threads,
+/// each owning its own `WhisperState`. A recording is split into chunks, so a long
+/// lecture keeps every worker busy.
+pub struct WhisperTranscriber {
+    config: Config,
+    /// Loaded on first use, every model used needs its own memory. Each model is downloaded
+    /// and loaded by the first job asking for it, the others wait on its cell instead of the map,
+    /// so loading one model doesn't hold up jobs using another.
+    models: Arc<Mutex<Models>>,
+}
+
+/// Which queue a task waits in. Idle workers take live windows first, so the captions
+/// of a recording in progress don't wait behind the chunks of a long upload.
+#[derive(Clone, Copy)]
+enum Lane {
+    Batch,
+    Live,
+}
+
+/// The worker threads of one model.
+struct Workers {
+    tasks: mpsc::Sender<Task>,
+    live: mpsc::Sender<Task>,
+    /// every token from end-of-text upwards is a special or timestamp token
+    eot: WhisperTokenId,
+    /// when the last transcription with the model finished
+    used: Mutex<Instant>,
+}
+
+impl Workers {
+    /// Loads the model onto the configured gpu, or the cpu if that fails.
+    fn start(path: &Path, config: &Config) -> Result<Self, WhisperError> {
+        match Self::start_on(path, config, config.gpu) {
+            Err(e) if config.gpu => {
+                eprintln!("failed to load whisper on the gpu, using the cpu: {}", e);
+                Self::start_on(path, config, false)
+            }
+            workers => workers,
+        }
+    }
+
+    fn start_on(path: &Path, config: &Config, gpu: bool) -> Result<Self, WhisperError> {
+        let ctx = WhisperContext::new_with_params(
+            &path.to_string_lossy(),
+            WhisperContextParameters {
+                use_gpu: gpu,
+                gpu_device: config.gpu_device,
+                ..Default::default()
+            },
+        )?;
+
+        let (tasks, tasks_rx) = mpsc::channel::<Task>(config.queue);
+        let (live, live_rx) = mpsc::channel::<Task>(config.queue);
+        let rx = Arc::new(tokio::sync::Mutex::new((live_rx, tasks_rx)));
+        // started from `spawn_blocking`, so there is a runtime to wait for both queues on
+        let runtime = Handle::current();
+        for i in 0..config.workers {
+            // create a state attached to the model
+            let mut state = ctx.create_state()?;
+            let (rx, runtime) = (rx.clone(), runtime.clone());
+            thread::Builder::new()
+                .name(format!("whisper-{}", i))
+                .spawn(move || loop {
+                    // the guard is dropped as soon as a task is received, so idle workers can wait for the next one
+                    let task = runtime.block_on(async {
+                        let (live, tasks) = &mut *rx.lock().await;
+                        select! {
+                            biased;
+                            Some(task) = live.recv() => Some(task),
+                            Some(task) = tasks.recv() => Some(task),
+                            else => None,
+                        }
+                    });
+                    let Some(task) = task else {
+                        break;
+                    };
+                    task(&mut state);
+                })
+                .expect("failed to spawn whisper worker");
+        }
+
+        Ok(Self {
+            tasks,
+            live,
+            eot: ctx.token_eot(),
+            used: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Runs `f` on the next free whisper state, waiting while the queue of its lane is full.
+    async fn run<T: Send + 'static>(
+        &self,
+        lane: Lane,
+        f: impl FnOnce(&mut WhisperState) -> Result<T, TranscriptionError> + Send + 'static,
+    ) -> Result<T, TranscriptionError> {
+        let (result, rx) = oneshot::channel();
+        let queue = match lane {
+            Lane::Batch => &self.tasks,
+            Lane::Live => &self.live,
+        };
+        queue
+            .send(Box::new(move |state| {
+                let _ = result.send(f(state));
+            }))
+            .await
+            .map_err(|_| TranscriptionError::Stopped)?;
+        rx.await.map_err(|_| TranscriptionError::Stopped)?
+    }
+}
+
+impl WhisperTranscriber {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            models: Default::default(),
+        }
+    }
+
+    /// Downloads and loads a model now instead of on its first use.
+    pub async fn load(&self, model: &str) -> Result<(), TranscriptionError> {
+        self.workers(model).await.map(|_| ())
+    }
+
+    async fn workers(&self, model: &str) -> Result<Arc<Workers>, TranscriptionError> {
+        let cell = self
+            .models
+            .lock()
+            .unwrap()
+            .entry(model.to_string())
+            .or_default()
+            .clone();
+        // a failed load leaves the cell empty, the next job tries again
+        let workers = cell
+            .get_or_try_init(|| async {
+                let path = models::ensure(&self.config, model).await?;
+                let config = self.config.clone();
+                let workers = spawn_blocking(move || Workers::start(&path, &config))
+                    .await
+                    .map_err(|_| TranscriptionError::Stopped)??;
+                Ok::<_, TranscriptionError>(Arc::new(workers))
+            })
+            .await?;
+        Ok(workers.clone())
+    }
+
+    /// Unloads a model other than the configured one once nobody used it for `IDLE`.
+    /// Its worker threads finish the tasks already queued and exit, freeing the model with them.
+    fn release(&self, model: &str, workers: Arc<Workers>) {
+        *workers.used.lock().unwrap() = Instant::now();
+        drop(workers);
+        if model == self.config.model {
+            return;
+        }
+        let (models, model) = (self.models.clone(), model.to_string());
+        spawn(async move {
+            sleep(IDLE).await;
+            let mut models = models.lock().unwrap();
+            let idle = models.get(&model).is_some_and(|cell| {
+                // the map holds the only reference to the cell and the workers, nobody is using them
+                Arc::strong_count(cell) == 1
+                    && cell.get().is_some_and(|workers| {
+                        Arc::strong_count(workers) == 1
+                            && workers.used.lock().unwrap().elapsed() >= IDLE
+                    })
+            });
+            if idle {
+                println!("Unloading whisper model {}", model);
+                models.remove(&model);
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl Transcriber for WhisperTranscriber {
+    /// Transcribes a file, its chunks are queued for the workers and run in parallel.
+    async fn transcribe(
+        &self,
+        file: String,
+        options: TranscriptionOptions,
+        progress: Box<dyn FnMut(i32) + Send>,
+    ) -> Result<Transcript, TranscriptionError> {
+        let vad = self.config.vad;
+        let preprocessing = options.preprocess.clone();
+        // decoding a long recording takes a while, so it stays off the async threads
+        let (samples, speech) = spawn_blocking(move || -> Result<_, AudioError> {
+            // we must convert to 16KHz mono f32 samples for the model
+            let mut samples = decode(&file)?;
+            preprocess::process(&mut samples, &preprocessing);
+            // whisper only gets the speech, it tends to hallucinate text into long silences
+            if vad {
+                let mut speech = vad::detect(&samples);
+                Ok((mem::take(&mut speech.samples), Some(speech)))
+            } else {
+                Ok((samples, None))
+            }
+        })
+        .await
+        .map_err(|_| TranscriptionError::Stopped)??;
+        self.run(samples, speech, options, Lane::Batch, progress).await
+    }
+
+    async fn transcribe_samples(
+        &self,
+        mut samples: Vec<f32>,
+        options: TranscriptionOptions,
+    ) -> Result<Transcript, TranscriptionError> {
+        preprocess::process(&mut samples, &options.preprocess);
+        self.run(samples, None, options, Lane::Live, Box::new(|_| {}))
+            .await
+    }
+}
+
+impl WhisperTranscriber {
+    /// `speech` maps the timestamps back if `samples` only holds the speech of the recording.
+    async fn run(
+        &self,
+        samples: Vec<f32>,
+        speech: Option<Speech>,
+        options: TranscriptionOptions,
+        lane: Lane,
+        progress: Box<dyn FnMut(i32) + Send>,
+    ) -> Result<Transcript, TranscriptionError> {
+        let model = options.model.clone().unwrap_or_else(|| self.config.model.clone());
+        let workers = self.workers(&model).await?;
+        let transcript = self
+            .run_on(&workers, samples, speech, options, lane, progress)
+            .await;
+        self.release(&model, workers);
+        transcript
+    }
+
+    async fn run_on(
+        &self,
+        workers: &Workers,
+        samples: Vec<f32>,
+        speech: Option<Speech>,
+        options: TranscriptionOptions,
+        lane: Lane,
+        progress: Box<dyn FnMut(i32) + Send>,
+    ) -> Result<Transcript, TranscriptionError> {
+        // an empty recording, or one where the vad found no speech, has nothing to transcribe
+        // and whisper fails on empty input
+        if samples.is_empty() {
+            // nothing to detect a language in
+            let language = options.language.unwrap_or_else(|| "en".to_string());
+            return Ok(Transcript {
+                text: String::new(),
+                segments: Vec::new(),
+                translation: (options.translate && language != "en").then(Vec::new),
+                language,
+                removed: Vec::new(),
+            });
+        }
+        let samples = Arc::new(samples);
+
+        let language = match options.language {
+            Some(language) => language,
+            None => {
+                let (samples, threads) = (samples.clone(), self.config.threads);
+                workers
+                    .run(lane, move |state| detect_language(state, &samples, threads))
+                    .await?
+            }
+        };
+        // whisper can only translate into english
+        let translate = options.translate && language != "en";
+
+        let chunks = chunks(samples.len());
+        let passes: &[bool] = if translate { &[false, true] } else { &[false] };
+        let progress = Arc::new(Mutex::new(ChunkProgress {
+            done: vec![0; chunks.len() * passes.len()],
+            report: progress,
+        }));
+        let tasks = passes
+            .iter()
+            .flat_map(|translate| chunks.iter().map(move |chunk| (*translate, chunk.clone())))
+            .enumerate()
+            .map(|(i, (translate, chunk))| {
+                let pass = Pass {
+                    language: language.clone(),
+                    translate,
+                    prompt: options.prompt.clone(),
+                    threads: self.config.threads,
+                    eot: workers.eot,
+                };
+                let (samples, progress) = (samples.clone(), progress.clone());
+                workers.run(lane, move |state| {
+                    let offset = (chunk.start / 16) as u32;
+                    let mut segments = full(state, &samples[chunk], &pass, move |p| {
+                        progress.lock().unwrap().update(i, p)
+                    })?;
+                    for segment in &mut segments {
+                        segment.start_ms += offset;
+                        segment.end_ms += offset;
+                    }
+                    Ok(segments)
+                })
+            });
+        let mut results = try_join_all(tasks).await?;
+        let translated = results.split_off(chunks.len());
+
+        let mut segments = merge(&chunks, results);
+        let mut translation = translate.then(|| merge(&chunks, translated));
+        if options.diarize {
+            // before the remapping, the timestamps still point into the samples whisper heard
+            segments = label_speakers(samples, segments, self.config.diarization_threshold).await?;
+            if let Some(translation) = &mut translation {
+                diarize::assign(&segments, translation);
+            }
+        }
+        // timestamps have to point into the original recording again
+        if let Some(speech) = &speech {
+            for segment in segments.iter_mut().chain(translation.iter_mut().flatten()) {
+                segment.start_ms = speech.remap(segment.start_ms);
+                segment.end_ms = speech.remap(segment.end_ms);
+            }
+        }
+
+        Ok(Transcript {
+            text: format_segments(&segments),
+            segments,
+            language,
+            translation,
+            removed: Vec::new(),
+        })
+    }
+}
+
+/// The progress of every chunk of a recording, reported as one number.
+struct ChunkProgress {
+    done: Vec<i32>,
+    report: Box<dyn FnMut(i32) + Send>,
+}
+
+impl ChunkProgress {
+    fn update(&mut self, chunk: usize, progress: i32) {
+        self.done[chunk] = progress;
+        let total = self.done.iter().sum::<i32>() / self.done.len() as i32;
+        (self.report)(total);
+    }
+}
+
+/// Sample ranges of the chunks, every chunk overlaps the next one by `OVERLAP_MS`.
+fn chunks(len: usize) -> Vec<Range<usize>> {
+    let (size, overlap) = (CHUNK_MS * 16, OVERLAP_MS * 16);
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    loop {
+        let end = (start + size).min(len);
+        chunks.push(start..end);
+        if end == len {
+            return chunks;
+        }
+        start = end - overlap;
+    }
+}
+
+/// Joins the segments of overlapping chunks. Every overlap is cut in the middle and each
+/// chunk keeps the segments that start on its side of the cut.
+fn merge(chunks: &[Range<usize>], results: Vec<Vec<Segment>>) -> Vec<Segment> {
+    let cut = |i: usize| ((chunks[i].end + chunks[i + 1].start) / 2 / 16) as u32;
+    let mut merged: Vec<Segment> = Vec::new();
+    for (i, segments) in results.into_iter().enumerate() {
+        let from = if i == 0 { 0 } else { cut(i - 1) };
+        let to = if i + 1 < chunks.len() {
+            cut(i)
+        } else {
+            u32::MAX
+        };
+        for segment in segments
+            .into_iter()
+            .filter(|s| s.start_ms >= from && s.start_ms < to)
+        {
+            // a sentence running over the cut is in both chunks
+            let duplicate = merged.last().is_some_and(|last| {
+                last.end_ms > segment.start_ms
+                    && last.text.to_lowercase() == segment.text.to_lowercase()
+            });
+            if !duplicate {
+                merged.push(segment);
+            }
+        }
+    }
+    for (index, segment) in merged.iter_mut().enumerate() {
+        segment.index = index as u32;
+    }
+    merged
+}
+
+/// Detects the spoken language from the first 30 seconds.
+fn detect_language(
+    state: &mut WhisperState,
+    samples: &[f32],
+    threads: usize,
+) -> Result<String, TranscriptionError> {
+    state.pcm_to_mel(&samples[..samples.len().min(30 * 16000)], threads)?;
+    let (id, _) = state.lang_detect(0, threads)?;
+    Ok(get_lang_str(id).unwrap_or("en").to_string())
+}
+
+/// Everything one whisper run needs besides the audio.
+struct Pass {
+    language: String,
+    /// whisper can only translate into english
+    translate: bool,
+    prompt: Option<String>,
+    threads: usize,
+    /// every token from end-of-text upwards is a special or timestamp token
+    eot: WhisperTokenId,
+}
+
+// https://codeberg.org/tazz4843/whisper-rs/src/branch/master/examples/basic_use.rs
+fn full(
+    state: &mut WhisperState,
+    samples: &[f32],
+    pass: &Pass,
+    progress: impl FnMut(i32) + 'static,
+) -> Result<Vec<Segment>, TranscriptionError> {
+    // the sampling strategy will determine how accurate your final output is going to be
+    // typically BeamSearch is more accurate at the cost of significantly increased CPU time
+    let mut params = FullParams::new(SamplingStrategy::BeamSearch {
+        // whisper.cpp defaults to a beam size of 5, a reasonable default
+        beam_size: 5,
+        // this parameter is currently unused but defaults to -1.0
+        patience: -1.0,
+    });
+
+    params.set_language(Some(&pass.language));
+    params.set_translate(pass.translate);
+    // the prompt reads like earlier text of the recording, so whisper prefers its spelling of terms
+    if let Some(prompt) = &pass.prompt {
+        params.set_initial_prompt(prompt);
+    }
+    params.set_n_threads(pass.threads as i32);
+
+    // we also explicitly disable anything that prints to stdout
+    // despite all of this you will still get things printing to stdout,
+    // be prepared to deal with it
+    params.set_print_special(false);
+    params.set_print_progress(false);
+    params.set_print_realtime(false);
+    params.set_print_timestamps(false);
+    params.set_progress_callback_safe(progress);
+
+    // now we can run the model
+    state.full(params, samples)?;
+
+    // fetch the results
+    let mut segments = Vec::new();
+    for (index, segment) in state.as_iter().enumerate() {
+        let probabilities: Vec<f32> = (0..segment.n_tokens())
+            .filter_map(|i| segment.get_token(i))
+            .filter(|token| token.token_id() < pass.eot)
+            .map(|token| token.token_probability())
+            .collect();
+        segments.push(Segment {
+            index: index as u32,
+            // these timestamps are in centiseconds (10s of milliseconds)
+            start_ms: segment.start_timestamp() as u32 * 10,
+            end_ms: segment.end_timestamp() as u32 * 10,
+            // this default Display implementation will result in any invalid UTF-8
+            // being converted into the Unicode replacement character, U+FFFD
+            text: segment.to_string().trim().to_string(),
+            probability: probabilities.iter().sum::<f32>() / probabilities.len().max(1) as f32,
+            speaker: None,
+        });
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start_ms: u32, end_ms: u32, text: &str) -> Segment {
+        Segment {
+            index: 0,
+            start_ms,
+            end_ms,
+            text: text.to_string(),
+            probability: 1.0,
+            speaker: None,
+        }
+    }
+
+    #[test]
+    fn short_recording_is_one_chunk() {
+        assert_eq!(chunks(16000), vec![0..16000]);
+        assert_eq!(chunks(CHUNK_MS * 16), vec![0..CHUNK_MS * 16]);
+    }
+
+    #[test]
+    fn chunks_overlap() {
+        let (size, overlap) = (CHUNK_MS * 16, OVERLAP_MS * 16);
+        let len = size * 2;
+        assert_eq!(
+            chunks(len),
+            vec![0..size, size - overlap..2 * size - overlap, 2 * size - 2 * overlap..len]
+        );
+    }
+
+    #[test]
+    fn merge_cuts_overlaps_in_the_middle() {
+        let chunks = vec![0..16 * 20_000, 16 * 10_000..16 * 30_000];
+        // the cut is at 15s, in the middle of the overlap from 10s to 20s
+        let merged = merge(
+            &chunks,
+            vec![
+                vec![segment(0, 12_000, "first"), segment(12_000, 18_000, "second")],
+                vec![segment(12_500, 17_000, "other second"), segment(17_000, 25_000, "third")],
+            ],
+        );
+        let texts: Vec<_> = merged.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["first", "second", "third"]);
+        let indices: Vec<_> = merged.iter().map(|s| s.index).collect();
+        assert_eq!(indices, [0, 1, 2]);
+    }
+
+    #[test]
+    fn merge_drops_sentences_running_over_the_cut() {
+        let chunks = vec![0..16 * 20_000, 16 * 10_000..16 * 30_000];
+        let merged = merge(
+            &chunks,
+            vec![
+                vec![segment(13_000, 17_000, "Over the cut.")],
+                vec![segment(15_000, 17_000, "over the cut."), segment(17_000, 20_000, "after")],
+            ],
+        );
+        let texts: Vec<_> = merged.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["Over the cut.", "after"]);
+    }
+}
@@ -1,50 +1,207 @@
 use axum::{extract::Multipart, http::StatusCode, response::IntoResponse, Extension};
-use sqlx::{query, Row};
-use tokio::{fs::File, io::AsyncWriteExt, spawn};
+use sqlx::{query, query_scalar, Row};
+use tokio::{
+    fs::{remove_file, File},
+    io::AsyncWriteExt,
+};
 use uuid::Uuid;
+use whisper_rs::{get_lang_id, get_lang_str};
 
-use crate::{datatypes::AppState, ai::sumarize, transcription::transcribe};
+use crate::{
+    audio::detect_extension, datatypes::AppState, jobs::JobOptions, models::MODELS,
+    preprocess::HIGHPASS_HZ, transcription::vocabulary_prompt,
+};
 
+/// Every file field is a recording named after the field.
+/// Text fields set the options for all recordings of the request:
+/// - `language`: whisper language code or name or `auto`, defaults to `auto`
+/// - `translate`: `true` to also translate the recordings into english
+/// - `course`: id of the course whose vocabulary is passed to whisper, empty for none
+/// - `vocabulary`: further terms for these recordings, separated by commas or new lines
+/// - `model`: whisper model to use, see `models::MODELS`, defaults to the configured one
+/// - `preprocess`: steps that clean up the audio first, comma separated out of
+///   `dc`, `highpass`, `denoise` and `normalize`, or `all`, none by default
+/// - `diarize`: `true` to label the segments with who is speaking
+/// - `highpass`: cutoff of the high-pass filter in Hz, defaults to `preprocess::HIGHPASS_HZ`
+/// - `summary_model`: Ollama model writing the summaries, defaults to the configured one
+/// - `temperature`, `top_p`, `repeat_penalty`: sampling of the summaries, default to the configured ones
 pub async fn upload(
     Extension(state): Extension<AppState>,
     mut multipart: Multipart,
 ) -> impl IntoResponse {
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
-        let file_name = format!("{}.wav", Uuid::new_v4());
-        let mut file = File::create(&file_name).await.unwrap();
-        file.write_all(&field.bytes().await.unwrap()).await.unwrap();
+    let mut files = Vec::new();
+    let options = match read(&state, &mut multipart, &mut files).await {
+        Ok(options) => options,
+        Err(status) => {
+            // recordings in front of the bad field are already written
+            remove(&files).await;
+            return status;
+        }
+    };
 
-        let row = query(
+    for (i, (name, file_name)) in files.iter().enumerate() {
+        let row = match query(
             r#"
             INSERT INTO entries (file, name)
             VALUES (?, ?)
             RETURNING id
         "#,
         )
-        .bind(&file_name)
+        .bind(file_name)
         .bind(name)
         .fetch_one(&state.pool)
         .await
-        .unwrap();
-
-        let pool = state.pool.clone();
-        spawn(async move {
-            let transcript = transcribe(file_name).await;
-            let summary = sumarize(transcript.clone()).await;
-            query(
-                r#"
-                UPDATE entries 
-                SET transcript = ?, summary = ?
-                WHERE id = ?
-            "#,
-            )
-            .bind(transcript).bind(summary)
-            .bind(row.get::<u32, &str>("id"))
-            .execute(&pool)
-            .await
-            .unwrap();
-        });
+        {
+            Ok(row) => row,
+            Err(e) => {
+                eprintln!("failed to add {}: {}", file_name, e);
+                // the recordings already added are notes now
+                remove(&files[i..]).await;
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+        };
+
+        // an entry without a job gets one from `JobQueue::recover` on the next start
+        if let Err(e) = state.jobs.enqueue(row.get("id"), options.clone()).await {
+            eprintln!("failed to queue {}: {}", file_name, e);
+            remove(&files[i + 1..]).await;
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
     }
     StatusCode::OK
 }
+
+async fn remove(files: &[(String, String)]) {
+    for (_, file_name) in files {
+        if let Err(e) = remove_file(file_name).await {
+            eprintln!("failed to remove {}: {}", file_name, e);
+        }
+    }
+}
+
+/// Reads the options of an upload and writes its recordings to disk, adding them to `files`.
+async fn read(
+    state: &AppState,
+    multipart: &mut Multipart,
+    files: &mut Vec<(String, String)>,
+) -> Result<JobOptions, StatusCode> {
+    let mut options = JobOptions::default();
+    let mut course: Option<u32> = None;
+    let mut vocabulary = String::new();
+    let mut highpass = false;
+    let mut cutoff = HIGHPASS_HZ;
+    // an aborted or malformed request fails here, the status tells which
+    while let Some(field) = multipart.next_field().await.map_err(|e| e.status())? {
+        let name = field.name().ok_or(StatusCode::BAD_REQUEST)?.to_string();
+        if field.file_name().is_none() {
+            let value = field.text().await.map_err(|e| e.status())?;
+            match name.as_str() {
+                "language" if value == "auto" => options.transcription.language = None,
+                // names like `german` are stored as their code, like the languages whisper detects
+                "language" => match get_lang_id(&value).and_then(get_lang_str) {
+                    Some(language) => options.transcription.language = Some(language.to_string()),
+                    None => return Err(StatusCode::BAD_REQUEST),
+                },
+                "translate" => match value.parse() {
+                    Ok(translate) => options.transcription.translate = translate,
+                    Err(_) => return Err(StatusCode::BAD_REQUEST),
+                },
+                "course" if value.is_empty() => course = None,
+                "course" => match value.parse() {
+                    Ok(id) => course = Some(id),
+                    Err(_) => return Err(StatusCode::BAD_REQUEST),
+                },
+                "vocabulary" => vocabulary = value,
+                "model" if value.is_empty() => options.transcription.model = None,
+                "model" if MODELS.contains(&value.as_str()) => {
+                    options.transcription.model = Some(value)
+                }
+                "model" => return Err(StatusCode::BAD_REQUEST),
+                "preprocess" => {
+                    let preprocess = &mut options.transcription.preprocess;
+                    for step in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                        match step {
+                            "dc" => preprocess.dc = true,
+                            "highpass" => highpass = true,
+                            "denoise" => preprocess.denoise = true,
+                            "normalize" => preprocess.normalize = true,
+                            "all" => {
+                                preprocess.dc = true;
+                                highpass = true;
+                                preprocess.denoise = true;
+                                preprocess.normalize = true;
+                            }
+                            _ => return Err(StatusCode::BAD_REQUEST),
+                        }
+                    }
+                }
+                "diarize" => match value.parse() {
+                    Ok(diarize) => options.transcription.diarize = diarize,
+                    Err(_) => return Err(StatusCode::BAD_REQUEST),
+                },
+                "highpass" => match value.parse() {
+                    // above 8KHz there is nothing left at 16KHz
+                    Ok(hz) if hz > 0.0 && hz < 8000.0 => cutoff = hz,
+                    _ => return Err(StatusCode::BAD_REQUEST),
+                },
+                "summary_model" => options.summary.model = (!value.is_empty()).then_some(value),
+                "temperature" => match value.parse() {
+                    Ok(t) if (0.0..=2.0).contains(&t) => options.summary.temperature = Some(t),
+                    _ => return Err(StatusCode::BAD_REQUEST),
+                },
+                "top_p" => match value.parse() {
+                    Ok(p) if p > 0.0 && p <= 1.0 => options.summary.top_p = Some(p),
+                    _ => return Err(StatusCode::BAD_REQUEST),
+                },
+                "repeat_penalty" => match value.parse() {
+                    Ok(p) if p > 0.0 && p < 10.0 => options.summary.repeat_penalty = Some(p),
+                    _ => return Err(StatusCode::BAD_REQUEST),
+                },
+                _ => {}
+            }
+            continue;
+        }
+
+        let bytes = field.bytes().await.map_err(|e| e.status())?;
+        let Some(extension) = detect_extension(&bytes) else {
+            return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        };
+        let file_name = format!("{}.{}", Uuid::new_v4(), extension);
+        let mut file = File::create(&file_name).await.map_err(|e| {
+            eprintln!("failed to create {}: {}", file_name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        // a recording cut short by a full disk is removed with the others
+        files.push((name, file_name.clone()));
+        file.write_all(&bytes).await.map_err(|e| {
+            eprintln!("failed to write {}: {}", file_name, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    }
+
+    if highpass {
+        options.transcription.preprocess.highpass = Some(cutoff);
+    }
+
+    if let Some(course) = course {
+        let Some(course_vocabulary): Option<String> = query_scalar(
+            r#"
+            SELECT vocabulary FROM courses
+            WHERE id = ?
+        "#,
+        )
+        .bind(course)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| {
+            eprintln!("failed to look up course {}: {}", course, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        else {
+            return Err(StatusCode::BAD_REQUEST);
+        };
+        vocabulary = format!("{}\n{}", course_vocabulary, vocabulary);
+    }
+    options.transcription.prompt = vocabulary_prompt(&vocabulary);
+    Ok(options)
+}
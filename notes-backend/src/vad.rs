@@ -0,0 +1,115 @@
+use std::ops::Range;
+
+/// 30ms frames at 16KHz.
+const FRAME: usize = 480;
+/// Speech has to be this much louder than the quietest parts of the recording.
+const THRESHOLD_DB: f32 = 12.0;
+/// Anything quieter is silence no matter how quiet the recording is.
+const MIN_DB: f32 = -55.0;
+/// Pauses shorter than this stay inside the region, whisper needs them to split sentences.
+/// Has to be more than twice `PADDING`.
+const MAX_GAP: usize = 16000;
+/// Kept before and after every region so word beginnings and endings aren't cut off.
+const PADDING: usize = 4000;
+/// Shorter regions are clicks and bumps of the microphone.
+const MIN_SPEECH: usize = 4000;
+
+/// The speech found in a recording, with the positions it had in the original.
+pub struct Speech {
+    /// only the speech regions, one after another
+    pub samples: Vec<f32>,
+    /// `(start in samples, start in the original)` for every region
+    regions: Vec<(usize, usize)>,
+}
+
+impl Speech {
+    /// Maps a time in `samples` back to the original recording.
+    pub fn remap(&self, ms: u32) -> u32 {
+        let sample = ms as usize * 16;
+        // the last region that starts before the time, a time on a boundary belongs to the region before it
+        let region = self
+            .regions
+            .iter()
+            .rev()
+            .find(|(start, _)| *start < sample)
+            .or(self.regions.first());
+        match region {
+            Some((start, original)) => ((sample - start + original) / 16) as u32,
+            None => ms,
+        }
+    }
+}
+
+/// Finds the regions of `samples` (16KHz mono) that contain speech with an energy based detector.
+/// Falls back to the whole recording if nothing looks like speech, a quiet recording is better
+/// transcribed badly than not at all.
+pub fn detect(samples: &[f32]) -> Speech {
+    let energy: Vec<f32> = samples
+        .chunks(FRAME)
+        .map(|frame| {
+            let power = frame.iter().map(|x| x * x).sum::<f32>() / frame.len() as f32;
+            10.0 * power.max(1e-10).log10()
+        })
+        .collect();
+
+    // the quietest tenth of the recording is taken as its noise floor
+    let mut sorted = energy.clone();
+    sorted.sort_by(f32::total_cmp);
+    let floor = sorted.get(sorted.len() / 10).copied().unwrap_or(MIN_DB);
+    let threshold = (floor + THRESHOLD_DB).max(MIN_DB);
+
+    let mut regions: Vec<Range<usize>> = Vec::new();
+    for (i, _) in energy.iter().enumerate().filter(|(_, e)| **e > threshold) {
+        let frame = i * FRAME..((i + 1) * FRAME).min(samples.len());
+        match regions.last_mut() {
+            Some(last) if frame.start - last.end <= MAX_GAP => last.end = frame.end,
+            _ => regions.push(frame),
+        }
+    }
+
+    let mut speech = Speech {
+        samples: Vec::new(),
+        regions: Vec::new(),
+    };
+    for region in regions.into_iter().filter(|r| r.len() >= MIN_SPEECH) {
+        let start = region.start.saturating_sub(PADDING);
+        // regions are more than two paddings apart, so they can't overlap
+        let end = (region.end + PADDING).min(samples.len());
+        speech.regions.push((speech.samples.len(), start));
+        speech.samples.extend_from_slice(&samples[start..end]);
+    }
+
+    if speech.samples.is_empty() {
+        speech.samples = samples.to_vec();
+        speech.regions = vec![(0, 0)];
+    }
+    speech
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remap_moves_times_into_their_region() {
+        // 1s of speech from 2s on, then 1s of speech from 10s on
+        let speech = Speech {
+            samples: Vec::new(),
+            regions: vec![(0, 2 * 16000), (16000, 10 * 16000)],
+        };
+        assert_eq!(speech.remap(0), 2000);
+        assert_eq!(speech.remap(500), 2500);
+        // a time on the boundary belongs to the region before it
+        assert_eq!(speech.remap(1000), 3000);
+        assert_eq!(speech.remap(1500), 10_500);
+    }
+
+    #[test]
+    fn remap_without_regions_keeps_times() {
+        let speech = Speech {
+            samples: Vec::new(),
+            regions: Vec::new(),
+        };
+        assert_eq!(speech.remap(1234), 1234);
+    }
+}
@@ -23,6 +23,12 @@ struct Recording {
     pub uploaded: bool,
 }
 
+#[derive(Serialize, Deserialize)]
+struct Course {
+    pub id: u32,
+    pub name: String,
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 #[tokio::main]
 pub async fn run() {
@@ -97,6 +103,7 @@ pub async fn run() {
             delete,
             upload,
             get_url,
+            get_courses,
             upload_files
         ])
         .run(tauri::generate_context!())
@@ -191,8 +198,30 @@ async fn get_url(app_handle: tauri::AppHandle) -> String {
     .get("url")
 }
 
+/// The courses of the backend at `url`, empty if it can't be reached.
+#[tauri::command]
+async fn get_courses(url: String) -> Vec<Course> {
+    let Ok(res) = Client::new().get(format!("{}/api/courses", url)).send().await else {
+        return Vec::new();
+    };
+    let Ok(body) = res.text().await else {
+        return Vec::new();
+    };
+    serde_json::from_str(&body).unwrap_or_default()
+}
+
 #[tauri::command]
-async fn upload_files(app_handle: tauri::AppHandle, url: String, files: Vec<u32>) {
+async fn upload_files(
+    app_handle: tauri::AppHandle,
+    url: String,
+    files: Vec<u32>,
+    language: String,
+    translate: bool,
+    preprocess: bool,
+    diarize: bool,
+    course: String,
+    vocabulary: String,
+) {
     query(
         r#"
         UPDATE config
@@ -225,7 +254,14 @@ async fn upload_files(app_handle: tauri::AppHandle, url: String, files: Vec<u32>
         .fetch_all(&app_handle.state::<AppData>().pool)
         .await
         .unwrap();
-    let mut form = Form::new();
+    let mut form = Form::new()
+        .text("language", language)
+        .text("translate", translate.to_string())
+        // every cleanup step, the backend picks its default high-pass cutoff
+        .text("preprocess", if preprocess { "all" } else { "" })
+        .text("diarize", diarize.to_string())
+        .text("course", course)
+        .text("vocabulary", vocabulary);
     for i in rows {
         let mut path = PathBuf::from(i.get::<String, &str>("file"));
         if path.set_extension("con.wav") && !path.exists() {
@@ -52,13 +52,68 @@ struct Recording {
 struct Upload {
     url: String,
     files: Vec<u32>,
+    language: String,
+    translate: bool,
+    preprocess: bool,
+    diarize: bool,
+    course: String,
+    vocabulary: String,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+struct Course {
+    id: u32,
+    name: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct GetCourses {
+    url: String,
+}
+
+const LANGUAGES: [(&str, &str); 11] = [
+    ("auto", "Detect language"),
+    ("en", "English"),
+    ("de", "German"),
+    ("fr", "French"),
+    ("es", "Spanish"),
+    ("it", "Italian"),
+    ("nl", "Dutch"),
+    ("pt", "Portuguese"),
+    ("pl", "Polish"),
+    ("ru", "Russian"),
+    ("zh", "Chinese"),
+];
+
 #[component]
 pub fn Upload() -> impl IntoView {
     let (files, add_file) = signal::<BTreeMap<Reverse<u32>, Recording>>(BTreeMap::new());
     let (ids, set_ids) = signal::<Vec<u32>>(Vec::new());
     let url = RwSignal::new("".to_string());
+    let language = RwSignal::new("auto".to_string());
+    let translate = RwSignal::new(false);
+    let preprocess = RwSignal::new(false);
+    let diarize = RwSignal::new(false);
+    let courses = RwSignal::new(Vec::<Course>::new());
+    let course = RwSignal::new("".to_string());
+    let vocabulary = RwSignal::new("".to_string());
+    let load_courses = move || {
+        spawn_local(async move {
+            courses.set(
+                from_value(
+                    invoke(
+                        "get_courses",
+                        to_value(&GetCourses {
+                            url: url.get_untracked(),
+                        })
+                        .unwrap(),
+                    )
+                    .await,
+                )
+                .unwrap_or_default(),
+            )
+        })
+    };
 
     Effect::new(move || {
         spawn_local(async move {
@@ -82,16 +137,45 @@ pub fn Upload() -> impl IntoView {
     });
     Effect::new(|| spawn_local(emit_argless("ready")));
     Effect::new(move || {
-        spawn_local(async move { url.set(from_value(invoke_argless("get_url").await).unwrap()) })
+        spawn_local(async move {
+            url.set(from_value(invoke_argless("get_url").await).unwrap());
+            load_courses();
+        })
     });
 
     view! {
         <div class="upload">
-            <input bind:value=url placeholder="prot://ip:port"/>
+            <input bind:value=url placeholder="prot://ip:port" on:change=move |_| load_courses()/>
+            <select bind:value=language>
+                {LANGUAGES.map(|(code, name)| view! { <option value=code>{name}</option> })}
+            </select>
+            <label>
+                <input type="checkbox" bind:checked=translate/>
+                "Translate to English"
+            </label>
+            <label>
+                <input type="checkbox" bind:checked=preprocess/>
+                "Clean up audio"
+            </label>
+            <label>
+                <input type="checkbox" bind:checked=diarize/>
+                "Label speakers"
+            </label>
+            <select bind:value=course>
+                <option value="">"No course"</option>
+                {move || courses.get().into_iter().map(|c| view! { <option value=c.id.to_string()>{c.name}</option> }).collect::<Vec<_>>()}
+            </select>
+            <input bind:value=vocabulary placeholder="Extra vocabulary, comma separated"/>
             <button on:click=move |_| {
                 spawn_local(async move {invoke("upload_files", to_value(&Upload {
                     url: url.get_untracked(),
-                    files: ids.get_untracked()
+                    files: ids.get_untracked(),
+                    language: language.get_untracked(),
+                    translate: translate.get_untracked(),
+                    preprocess: preprocess.get_untracked(),
+                    diarize: diarize.get_untracked(),
+                    course: course.get_untracked(),
+                    vocabulary: vocabulary.get_untracked()
                 }).unwrap()).await;})
             }>Upload</button>
         </div>